@@ -0,0 +1,243 @@
+use std::cmp::Ordering;
+use std::fmt;
+use std::num::ParseIntError;
+use std::str::FromStr;
+
+use thiserror::Error;
+
+use crate::comands::BlockchainError;
+use crate::encoding::{Decode, DecodeError, Encode, Reader};
+
+// Наибольшее число знаков после запятой у суммы и у актива.
+pub const MAX_DECIMALS: u8 = 18;
+
+// Неотрицательная сумма актива в наименьших единицах. Арифметика только проверяемая:
+// переполнение и уход в минус возвращают None, а не заворачиваются.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Amount(u128);
+
+impl Amount {
+    pub const ZERO: Amount = Amount(0);
+    pub const MAX: Amount = Amount(u128::MAX);
+
+    pub const fn new(units: u128) -> Self {
+        Self(units)
+    }
+
+    pub fn units(self) -> u128 {
+        self.0
+    }
+
+    pub fn is_zero(self) -> bool {
+        self.0 == 0
+    }
+
+    pub fn checked_add(self, other: Amount) -> Option<Amount> {
+        self.0.checked_add(other.0).map(Self)
+    }
+
+    pub fn checked_sub(self, other: Amount) -> Option<Amount> {
+        self.0.checked_sub(other.0).map(Self)
+    }
+
+    pub fn saturating_sub(self, other: Amount) -> Amount {
+        Self(self.0.saturating_sub(other.0))
+    }
+}
+
+impl fmt::Display for Amount {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+// Сумма задается целым неотрицательным числом; знак минус не принимается.
+impl FromStr for Amount {
+    type Err = ParseIntError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        s.parse().map(Self)
+    }
+}
+
+impl Encode for Amount {
+    fn encode(&self, out: &mut Vec<u8>) {
+        self.0.encode(out);
+    }
+}
+
+impl Decode for Amount {
+    fn decode(reader: &mut Reader) -> Result<Self, DecodeError> {
+        Ok(Self(Decode::decode(reader)?))
+    }
+}
+
+// Десятичная сумма с фиксированной точкой: mantissa / 10^scale. Так суммы задаются
+// в командах; в наименьшие единицы актива они переводятся по его числу знаков decimals.
+// Незначащие нули дробной части отбрасываются, поэтому у равных сумм одна кодировка.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Decimal {
+    mantissa: u128,
+    scale: u8,
+}
+
+#[derive(Debug, Error)]
+#[error("invalid amount: {0}")]
+pub struct ParseDecimalError(String);
+
+impl Decimal {
+    pub const fn new(mut mantissa: u128, mut scale: u8) -> Self {
+        assert!(scale <= MAX_DECIMALS);
+        while scale > 0 && mantissa.is_multiple_of(10) {
+            mantissa /= 10;
+            scale -= 1;
+        }
+        Self { mantissa, scale }
+    }
+
+    // Сумма из наименьших единиц актива с decimals знаками после запятой.
+    pub fn from_units(value: Amount, decimals: u8) -> Self {
+        Self::new(value.units(), decimals.min(MAX_DECIMALS))
+    }
+
+    pub fn is_zero(self) -> bool {
+        self.mantissa == 0
+    }
+
+    // Переводит сумму в наименьшие единицы актива с decimals знаками после запятой.
+    // Сумма с большим числом знаков, чем у актива, отклоняется, а не округляется.
+    pub fn to_units(self, decimals: u8) -> Result<Amount, BlockchainError> {
+        if self.scale > decimals {
+            return Err(BlockchainError::ExcessPrecision);
+        }
+        10u128
+            .checked_pow(u32::from(decimals - self.scale))
+            .and_then(|factor| self.mantissa.checked_mul(factor))
+            .map(Amount::new)
+            .ok_or(BlockchainError::Overflow)
+    }
+
+    // Целая часть и дробная часть, приведенная к MAX_DECIMALS знакам, — для сравнения
+    // сумм с разным scale без переполнения.
+    fn parts(self) -> (u128, u128) {
+        let divisor = 10u128.pow(u32::from(self.scale));
+        (
+            self.mantissa / divisor,
+            self.mantissa % divisor * 10u128.pow(u32::from(MAX_DECIMALS - self.scale)),
+        )
+    }
+}
+
+impl PartialEq for Decimal {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Decimal {}
+
+impl PartialOrd for Decimal {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Decimal {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.parts().cmp(&other.parts())
+    }
+}
+
+impl fmt::Display for Decimal {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (integer, _) = self.parts();
+        if self.scale == 0 {
+            return write!(f, "{}", integer);
+        }
+        let fraction = self.mantissa % 10u128.pow(u32::from(self.scale));
+        write!(
+            f,
+            "{}.{:0width$}",
+            integer,
+            fraction,
+            width = usize::from(self.scale)
+        )
+    }
+}
+
+// Сумма задается как "123" или "123.45"; незначащие нули в конце дробной части
+// отбрасываются, так что "1.50" — это сумма с одним знаком после запятой.
+impl FromStr for Decimal {
+    type Err = ParseDecimalError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let error = || ParseDecimalError(s.to_string());
+        let (integer, fraction) = s.split_once('.').unwrap_or((s, ""));
+        let fraction = fraction.trim_end_matches('0');
+        let is_digits = |part: &str| part.bytes().all(|byte| byte.is_ascii_digit());
+        if integer.is_empty()
+            || !is_digits(integer)
+            || !is_digits(fraction)
+            || s.ends_with('.')
+            || fraction.len() > usize::from(MAX_DECIMALS)
+        {
+            return Err(error());
+        }
+        let mantissa = format!("{}{}", integer, fraction)
+            .parse()
+            .map_err(|_| error())?;
+        Ok(Self::new(mantissa, fraction.len() as u8))
+    }
+}
+
+impl Encode for Decimal {
+    fn encode(&self, out: &mut Vec<u8>) {
+        self.mantissa.encode(out);
+        self.scale.encode(out);
+    }
+}
+
+impl Decode for Decimal {
+    fn decode(reader: &mut Reader) -> Result<Self, DecodeError> {
+        let mantissa = u128::decode(reader)?;
+        let scale = u8::decode(reader)?;
+        // Принимается только нормализованная форма: у одной суммы одна кодировка.
+        if scale > MAX_DECIMALS || (scale > 0 && mantissa.is_multiple_of(10)) {
+            return Err(DecodeError::InvalidValue {
+                type_name: "Decimal",
+            });
+        }
+        Ok(Self { mantissa, scale })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::encoding::{decode_versioned, encode_versioned, ENCODING_VERSION};
+
+    #[test]
+    // проверяет, что равные суммы с разным числом нулей в дробной части совпадают
+    // по кодировке и одинаково переводятся в единицы актива, а ненормализованная
+    // кодировка отклоняется.
+    fn test_decimal_trailing_zeros_are_normalised() {
+        let (long, short) = (Decimal::new(150, 2), Decimal::new(15, 1));
+        assert_eq!(long, short);
+        assert_eq!(encode_versioned(&long), encode_versioned(&short));
+        assert_eq!(long.to_units(1).unwrap(), Amount::new(15));
+        assert_eq!(long.to_string(), "1.5");
+        assert_eq!(Decimal::new(0, 18).to_units(0).unwrap(), Amount::ZERO);
+
+        let mut bytes = vec![ENCODING_VERSION];
+        150u128.encode(&mut bytes);
+        2u8.encode(&mut bytes);
+        assert!(matches!(
+            decode_versioned::<Decimal>(&bytes),
+            Err(DecodeError::InvalidValue {
+                type_name: "Decimal"
+            })
+        ));
+        let decoded: Decimal = decode_versioned(&encode_versioned(&short)).unwrap();
+        assert_eq!(decoded, short);
+    }
+}
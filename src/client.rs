@@ -1,14 +1,17 @@
 use std::{
     collections::HashMap,
+    sync::atomic::{AtomicU64, Ordering},
     sync::mpsc::{Receiver, Sender},
+    sync::Mutex,
 };
 
-use ursa::{
-    keys::{PrivateKey, PublicKey},
-    signatures::{prelude::Ed25519Sha512, SignatureScheme},
-};
+use ursa::keys::{PrivateKey, PublicKey};
 
-use crate::comands::{Block, Command, Transaction};
+use crate::amount::Decimal;
+use crate::comands::{AssetDefinition, BlockHeader, Command, Transaction};
+use crate::crypto::MerkleProof;
+use crate::network::Message;
+use crate::state_tree::BalanceProof;
 use thiserror::Error;
 
 #[derive(Error, Debug)]
@@ -48,58 +51,122 @@ pub enum ClientError {
 
     #[error("transaction commission: {0}")]
     TransactionCommission(String),
+
+    #[error("bond: {0}")]
+    Bond(String),
+
+    #[error("unbond: {0}")]
+    Unbond(String),
+
+    #[error("delegate: {0}")]
+    Delegate(String),
+
+    #[error("register asset: {0}")]
+    RegisterAsset(String),
 }
+// Сообщение пира клиенту.
+#[derive(Debug)]
+pub enum Update {
+    // Событие для вывода пользователю.
+    Text(String),
+    // Транзакция подписанта signer отклонена; следующую его транзакцию нужно
+    // подписать с nonce next_nonce.
+    Rejected {
+        signer: PublicKey,
+        next_nonce: u64,
+        reason: String,
+    },
+}
+
+// Клиент разделяется между CLI и потоком обновлений, поэтому nonce и приемник
+// обновлений защищены сами по себе, а не блокировкой всего клиента.
 #[derive(Debug)]
 pub struct Client {
-    tx: Sender<Block>,
+    tx: Sender<Message>,
     public_key: PublicKey,
     private_key: PrivateKey,
-    peer_rx: Receiver<String>,
+    peer_rx: Mutex<Receiver<Update>>,
+    // nonce следующей транзакции; создание счета идет с nonce 0.
+    // Если пир отклоняет транзакцию, nonce возвращается к указанному им.
+    next_nonce: AtomicU64,
 }
 
 impl Client {
     pub fn new(
-        tx: Sender<Block>,
+        tx: Sender<Message>,
         public_key: PublicKey,
         private_key: PrivateKey,
-        peer_rx: Receiver<String>,
+        peer_rx: Receiver<Update>,
     ) -> Self {
         Self {
             tx,
             public_key,
             private_key,
-            peer_rx,
+            peer_rx: Mutex::new(peer_rx),
+            next_nonce: AtomicU64::new(0),
         }
     }
     // метод для получения обновлений от Peer
     pub fn receive_updates(&self) {
-        while let Ok(message) = self.peer_rx.recv() {
-            println!("Received update: {}", message);
+        let peer_rx = self.peer_rx.lock().unwrap();
+        while let Ok(update) = peer_rx.recv() {
+            self.apply_update(&update);
+            match update {
+                Update::Text(message) => println!("Received update: {}", message),
+                Update::Rejected { reason, .. } => {
+                    println!("Received update: Transaction rejected: {}", reason)
+                }
+            }
+        }
+    }
+
+    // Отклоненная транзакция клиента не израсходовала свой nonce: следующая
+    // транзакция подписывается с nonce, который сообщил пир.
+    fn apply_update(&self, update: &Update) {
+        if let Update::Rejected {
+            signer, next_nonce, ..
+        } = update
+        {
+            if *signer == self.public_key {
+                self.next_nonce.store(*next_nonce, Ordering::SeqCst);
+            }
         }
     }
 
-    // функция send_transaction, отвечает за создание блока из транзакции, подписание его закрытым ключом и отправку в канал.
-    fn send_transaction(&self, transaction: Transaction) -> Result<(), ClientError> {
-        let data = vec![transaction];
-        let signature = Ed25519Sha512::new()
-            .sign(format!("{:?}", &data).as_bytes(), &self.private_key)
+    // Проверяет доказательство включения транзакции в блок по его заголовку, не загружая блок целиком.
+    pub fn verify_transaction_inclusion(
+        header: &BlockHeader,
+        transaction: &Transaction,
+        proof: &MerkleProof,
+    ) -> bool {
+        proof.verify(&header.transactions_root, &transaction.hash())
+    }
+
+    // Проверяет доказательство остатка актива на счете по корню состояния из заголовка блока.
+    pub fn verify_balance(
+        header: &BlockHeader,
+        account_id: u32,
+        asset_id: &str,
+        proof: &BalanceProof,
+    ) -> bool {
+        proof.verify(header, account_id, asset_id)
+    }
+
+    // функция send_transaction, отвечает за подписание команды закрытым ключом и отправку транзакции пиру.
+    fn send_transaction(&self, command: Command) -> Result<(), ClientError> {
+        let nonce = self.next_nonce.load(Ordering::SeqCst);
+        let transaction = Transaction::new(command, nonce, &self.public_key, &self.private_key)
             .map_err(|e| ClientError::SignTransaction(e.to_string()))?;
         self.tx
-            .send(Block {
-                signature,
-                data,
-                signer_public_key: self.public_key.clone(),
-                previous_block_hash: None,
-            })
+            .send(Message::Transaction(transaction))
             .map_err(|e| ClientError::SendTransaction(e.to_string()))?;
+        self.next_nonce.store(nonce + 1, Ordering::SeqCst);
         Ok(())
     }
 
     pub fn create_account(&self) -> Result<(), ClientError> {
-        self.send_transaction(Transaction {
-            command: Command::CreateAccount {
-                public_key: self.public_key.to_string(),
-            },
+        self.send_transaction(Command::CreateAccount {
+            public_key: self.public_key.to_string(),
         })
         .map_err(|_| ClientError::CreateAccount("create account".to_string()))
     }
@@ -108,16 +175,14 @@ impl Client {
         &self,
         from_account_id: u32,
         to_account_id: u32,
-        value: i32,
+        value: Decimal,
         asset_id: String,
     ) -> Result<(), ClientError> {
-        self.send_transaction(Transaction {
-            command: Command::TransferFunds {
-                from_account_id,
-                to_account_id,
-                value,
-                asset_id,
-            },
+        self.send_transaction(Command::TransferFunds {
+            from_account_id,
+            to_account_id,
+            value,
+            asset_id,
         })
         .map_err(|_| ClientError::TransferFunds("transfer funds".to_string()))
     }
@@ -128,12 +193,10 @@ impl Client {
         name: Option<String>,
         contact_info: Option<String>,
     ) -> Result<(), ClientError> {
-        self.send_transaction(Transaction {
-            command: Command::UpdateAccount {
-                account_id,
-                name,
-                contact_info,
-            },
+        self.send_transaction(Command::UpdateAccount {
+            account_id,
+            name,
+            contact_info,
         })
         .map_err(|_| ClientError::UpdateAccount("update account".to_string()))
     }
@@ -143,12 +206,10 @@ impl Client {
         contract_id: String,
         params: HashMap<String, String>,
     ) -> Result<(), ClientError> {
-        self.send_transaction(Transaction {
-            command: Command::ExecuteSmartContract {
-                account_id,
-                contract_id,
-                params,
-            },
+        self.send_transaction(Command::ExecuteSmartContract {
+            account_id,
+            contract_id,
+            params,
         })
         .map_err(|_| ClientError::ExecuteSmartContract("execute smart contract".to_string()))
     }
@@ -157,16 +218,14 @@ impl Client {
         &self,
         account_id: u32,
         asset_id: String,
-        value: i32,
+        value: Decimal,
         redeem_in_asset_id: String,
     ) -> Result<(), ClientError> {
-        self.send_transaction(Transaction {
-            command: Command::RedeemAsset {
-                account_id,
-                asset_id,
-                value,
-                redeem_in_asset_id,
-            },
+        self.send_transaction(Command::RedeemAsset {
+            account_id,
+            asset_id,
+            value,
+            redeem_in_asset_id,
         })
         .map_err(|_| ClientError::ReleaseAsset("release asset".to_string()))
     }
@@ -177,12 +236,10 @@ impl Client {
         to_account_id: u32,
         asset_id: String,
     ) -> Result<(), ClientError> {
-        self.send_transaction(Transaction {
-            command: Command::TransferAsset {
-                from_account_id,
-                to_account_id,
-                asset_id,
-            },
+        self.send_transaction(Command::TransferAsset {
+            from_account_id,
+            to_account_id,
+            asset_id,
         })
         .map_err(|_| ClientError::TransferAsset("transfer asset".to_string()))
     }
@@ -191,16 +248,14 @@ impl Client {
         &self,
         account_id: u32,
         asset_id: String,
-        value: i32,
+        value: Decimal,
         redeem_in_asset_id: String,
     ) -> Result<(), ClientError> {
-        self.send_transaction(Transaction {
-            command: Command::RedeemAsset {
-                account_id,
-                asset_id,
-                value,
-                redeem_in_asset_id,
-            },
+        self.send_transaction(Command::RedeemAsset {
+            account_id,
+            asset_id,
+            value,
+            redeem_in_asset_id,
         })
         .map_err(|_| ClientError::RedeemAsset("redeem asset".to_string()))
     }
@@ -208,15 +263,13 @@ impl Client {
     pub fn add_funds(
         &self,
         account_id: u32,
-        value: i32,
+        value: Decimal,
         asset_id: String,
     ) -> Result<(), ClientError> {
-        self.send_transaction(Transaction {
-            command: Command::AddFunds {
-                account_id,
-                value,
-                asset_id,
-            },
+        self.send_transaction(Command::AddFunds {
+            account_id,
+            value,
+            asset_id,
         })
         .map_err(|_| ClientError::AddFunds("add funds".to_string()))
     }
@@ -225,22 +278,121 @@ impl Client {
         &self,
         account_id: u32,
         asset_id: String,
-        value: i32,
+        value: Decimal,
     ) -> Result<(), ClientError> {
-        self.send_transaction(Transaction {
-            command: Command::IssueAsset {
-                account_id,
-                asset_id,
-                value,
-            },
+        self.send_transaction(Command::IssueAsset {
+            account_id,
+            asset_id,
+            value,
         })
         .map_err(|_| ClientError::IssueAsset("issue asset".to_string()))
     }
 
-    pub fn transaction_commission(&self, account_id: u32, value: i32) -> Result<(), ClientError> {
-        self.send_transaction(Transaction {
-            command: Command::TransactionCommission { account_id, value },
+    pub fn transaction_commission(
+        &self,
+        account_id: u32,
+        value: Decimal,
+    ) -> Result<(), ClientError> {
+        self.send_transaction(Command::TransactionCommission { account_id, value })
+            .map_err(|_| ClientError::TransactionCommission("transaction commission".to_string()))
+    }
+
+    // public_key — открытый ключ пира-валидатора в hex, которым он подписывает блоки;
+    // key_signature — подпись этого ключа для счета (Identity::sign_bond).
+    pub fn bond(
+        &self,
+        account_id: u32,
+        public_key: String,
+        value: Decimal,
+        key_signature: Vec<u8>,
+    ) -> Result<(), ClientError> {
+        self.send_transaction(Command::Bond {
+            account_id,
+            public_key,
+            value,
+            key_signature,
         })
-        .map_err(|_| ClientError::TransactionCommission("transaction commission".to_string()))
+        .map_err(|_| ClientError::Bond("bond".to_string()))
+    }
+
+    pub fn unbond(
+        &self,
+        account_id: u32,
+        validator_id: u32,
+        value: Decimal,
+    ) -> Result<(), ClientError> {
+        self.send_transaction(Command::Unbond {
+            account_id,
+            validator_id,
+            value,
+        })
+        .map_err(|_| ClientError::Unbond("unbond".to_string()))
+    }
+
+    pub fn delegate(
+        &self,
+        account_id: u32,
+        validator_id: u32,
+        value: Decimal,
+    ) -> Result<(), ClientError> {
+        self.send_transaction(Command::Delegate {
+            account_id,
+            validator_id,
+            value,
+        })
+        .map_err(|_| ClientError::Delegate("delegate".to_string()))
+    }
+
+    // Эмитентом актива становится счет definition.issuer_id; он должен принадлежать клиенту.
+    pub fn register_asset(
+        &self,
+        asset_id: String,
+        definition: AssetDefinition,
+    ) -> Result<(), ClientError> {
+        self.send_transaction(Command::RegisterAsset {
+            asset_id,
+            definition,
+        })
+        .map_err(|_| ClientError::RegisterAsset("register asset".to_string()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::mpsc::channel;
+
+    use ursa::signatures::{prelude::Ed25519Sha512, SignatureScheme};
+
+    use super::*;
+
+    #[test]
+    // проверяет, что после отклонения транзакции клиент подписывает следующую с nonce от пира.
+    fn test_rejection_rolls_back_nonce() {
+        let (tx, rx) = channel();
+        let (_peer_tx, peer_rx) = channel();
+        let (public_key, private_key) = Ed25519Sha512::new().keypair(None).unwrap();
+        let client = Client::new(tx, public_key.clone(), private_key, peer_rx);
+        let nonce = || match rx.try_recv().unwrap() {
+            Message::Transaction(transaction) => transaction.nonce,
+            message => panic!("unexpected message {:?}", message),
+        };
+        client.create_account().unwrap();
+        client
+            .add_funds(1, Decimal::new(1, 0), "currency".to_string())
+            .unwrap();
+        assert_eq!((nonce(), nonce()), (0, 1));
+
+        let (other, _) = Ed25519Sha512::new().keypair(None).unwrap();
+        for signer in [other, public_key] {
+            client.apply_update(&Update::Rejected {
+                signer,
+                next_nonce: 1,
+                reason: "unknown asset".to_string(),
+            });
+        }
+        client
+            .add_funds(1, Decimal::new(1, 0), "currency".to_string())
+            .unwrap();
+        assert_eq!(nonce(), 1);
     }
 }
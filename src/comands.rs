@@ -1,28 +1,103 @@
-use crate::crypto::Hash;
-use std::collections::HashMap;
+use crate::amount::{Amount, Decimal, MAX_DECIMALS};
+use crate::crypto::{self, Hash};
+use crate::encoding::{
+    decode_versioned, encode_versioned, Decode, DecodeError, Encode, Reader, ENCODING_VERSION,
+};
+use crate::identity::{parse_public_key, to_hex};
+use crate::staking::{is_epoch_end, Staking};
+use std::collections::{BTreeMap, HashMap};
+use std::time::{SystemTime, UNIX_EPOCH};
 use thiserror::Error;
-use ursa::keys::PublicKey;
-#[derive(Debug, Clone)]
+use ursa::keys::{PrivateKey, PublicKey};
+use ursa::signatures::{prelude::Ed25519Sha512, SignatureScheme};
+use ursa::CryptoError;
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct Account {
     pub public_key: String,
     pub name: Option<String>,
     pub contact_info: Option<String>,
     pub balance: u32,
+    // Nonce последней применённой транзакции этого счета.
+    pub nonce: u64,
 }
 
 pub type Accounts = HashMap<u32, Account>;
 
 pub type Assets = HashMap<(u32, String), Asset>;
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct Asset {
-    pub value: i32,
+    pub value: Amount,
     pub owner_id: u32,
 }
 
+// Состояние мира, к которому применяются транзакции.
+#[derive(Debug, Clone, Default)]
+pub struct State {
+    pub accounts: Accounts,
+    pub assets: Assets,
+    pub staking: Staking,
+    // Масса каждого актива: сколько выпущено и сожжено за всю историю.
+    pub supply: BTreeMap<String, Supply>,
+    // Реестр активов: выпускать актив можно только после регистрации.
+    pub definitions: BTreeMap<String, AssetDefinition>,
+    // Журнал выпусков командами AddFunds и IssueAsset в порядке выполнения.
+    pub mints: Vec<MintRecord>,
+}
+
+// Описание актива в реестре. Масса актива не может превысить max_supply, если он задан.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AssetDefinition {
+    pub name: String,
+    // Число знаков после запятой при отображении сумм.
+    pub decimals: u8,
+    // Счет эмитента: только он выпускает актив (кроме казначейства).
+    pub issuer_id: u32,
+    // Предельная масса в наименьших единицах актива.
+    pub max_supply: Option<Amount>,
+    // Разрешены ли переводы актива между счетами.
+    pub transferable: bool,
+    // Можно ли обменять актив на другой командой RedeemAsset.
+    pub redeemable: bool,
+}
+
+// Запись о выпуске: на счет account_id выпущено value актива asset_id.
+#[derive(Debug, Clone)]
+pub struct MintRecord {
+    pub account_id: u32,
+    pub asset_id: String,
+    pub value: Amount,
+}
+
+// Параметры сети. Влияют на результат выполнения блоков, поэтому должны совпадать у всех пиров.
+#[derive(Debug, Clone, Default)]
+pub struct Params {
+    // Награда автору за каждый блок в "currency"; переводится в наименьшие единицы
+    // по описанию актива и выпускается не выше его предельной массы.
+    pub block_reward: Decimal,
+    // Счет казначейства, которому разрешено выпускать любые активы командой AddFunds.
+    // С ним "currency" регистрируется в начальном состоянии, и казначейство — ее эмитент.
+    pub treasury: Option<u32>,
+}
+
+// Число знаков "currency", зарегистрированной в начальном состоянии.
+pub const CURRENCY_DECIMALS: u8 = 2;
+
+// Учет массы актива для аудита инфляции. Разность выпущенного и сожженного равна сумме
+// остатков на счетах (для "currency" — вместе со стейкингом).
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct Supply {
+    pub issued: Amount,
+    pub burned: Amount,
+}
+
+// Транзакция подписывается владельцем счета; nonce должен расти на единицу с каждой транзакцией счета.
 #[derive(Debug, Clone)]
 pub struct Transaction {
     pub command: Command,
+    pub signer_public_key: PublicKey,
+    pub nonce: u64,
+    pub signature: Vec<u8>,
 }
 
 #[derive(Debug, Clone)]
@@ -32,13 +107,13 @@ pub enum Command {
     },
     AddFunds {
         account_id: u32,
-        value: i32,
+        value: Decimal,
         asset_id: String,
     },
     TransferFunds {
         from_account_id: u32,
         to_account_id: u32,
-        value: i32,
+        value: Decimal,
         asset_id: String,
     },
     UpdateAccount {
@@ -54,7 +129,7 @@ pub enum Command {
     IssueAsset {
         account_id: u32,
         asset_id: String,
-        value: i32,
+        value: Decimal,
     },
     TransferAsset {
         from_account_id: u32,
@@ -64,21 +139,83 @@ pub enum Command {
     RedeemAsset {
         account_id: u32,
         asset_id: String,
-        value: i32,
+        value: Decimal,
         redeem_in_asset_id: String,
     },
     TransactionCommission {
         account_id: u32,
-        value: i32,
+        value: Decimal,
+    },
+    // Регистрирует актив asset_id; подписывает эмитент из описания.
+    RegisterAsset {
+        asset_id: String,
+        definition: AssetDefinition,
+    },
+    // Регистрирует счет валидатором с ключом пира public_key (hex) и ставит собственный стейк.
+    // key_signature — подпись ключом валидатора над bond_signing_bytes(account_id):
+    // доказывает, что счет распоряжается этим ключом.
+    Bond {
+        account_id: u32,
+        public_key: String,
+        value: Decimal,
+        key_signature: Vec<u8>,
+    },
+    // Выводит стейк, поставленный счетом за валидатора validator_id.
+    Unbond {
+        account_id: u32,
+        validator_id: u32,
+        value: Decimal,
+    },
+    // Ставит стейк счета за зарегистрированного валидатора validator_id.
+    Delegate {
+        account_id: u32,
+        validator_id: u32,
+        value: Decimal,
+    },
+    // Доказательство двойной подписи валидатора. Может отправить кто угодно с nonce 0.
+    ReportEquivocation {
+        evidence: Box<Equivocation>,
     },
 }
 
+// Заголовок блока. Подпись и хэш блока покрывают только заголовок,
+// а транзакции привязаны к нему через transactions_root.
+#[derive(Debug, Clone)]
+pub struct BlockHeader {
+    pub height: u64,
+    // Время создания блока в миллисекундах от начала эпохи Unix.
+    pub timestamp: u64,
+    pub proposer_id: u8,
+    // Раунд выбора ведущего на этой высоте, в котором предложен блок.
+    pub round: u32,
+    pub previous_block_hash: Option<Hash>,
+    pub transactions_root: Hash,
+    // Корень состояния после применения транзакций блока.
+    pub state_root: Hash,
+}
+
 #[derive(Debug, Clone)]
 pub struct Block {
+    pub header: BlockHeader,
     pub data: Vec<Transaction>,
     pub signature: Vec<u8>,
     pub signer_public_key: PublicKey,
-    pub previous_block_hash: Option<Hash>,
+}
+
+// Заголовок блока с подписью автора — без транзакций, которые для доказательств не нужны.
+#[derive(Debug, Clone)]
+pub struct SignedHeader {
+    pub header: BlockHeader,
+    pub signature: Vec<u8>,
+    pub signer_public_key: PublicKey,
+}
+
+// Два разных заголовка, подписанных одним валидатором на одной высоте в одном раунде.
+// Новый блок в следующем раунде той же высоты нарушением не считается.
+#[derive(Debug, Clone)]
+pub struct Equivocation {
+    pub first: SignedHeader,
+    pub second: SignedHeader,
 }
 #[derive(Error, Debug)]
 
@@ -97,60 +234,313 @@ pub enum BlockchainError {
 
     #[error("parsing parameters for contract failed")]
     FailedToParseParameters,
+
+    #[error("invalid transaction signature")]
+    InvalidSignature,
+
+    #[error("invalid nonce: expected {expected}, got {got}")]
+    InvalidNonce { expected: u64, got: u64 },
+
+    #[error("signer is not authorized to perform this operation")]
+    Unauthorized,
+
+    #[error("account already exists")]
+    AccountExists,
+
+    #[error("amount must be positive")]
+    InvalidAmount,
+
+    #[error("validator not found")]
+    ValidatorNotFound,
+
+    #[error("total stake would exceed the consensus limit")]
+    StakeLimitExceeded,
+
+    #[error("evidence is older than the unbonding window")]
+    EvidenceExpired,
+
+    #[error("evidence has already been processed")]
+    EvidenceAlreadyProcessed,
+
+    #[error("validator key is already registered by another account")]
+    ValidatorKeyInUse,
+
+    #[error("invalid equivocation evidence")]
+    InvalidEvidence,
+
+    #[error("asset {0} is not registered")]
+    UnknownAsset(String),
+
+    #[error("asset is already registered")]
+    AssetExists,
+
+    #[error("asset supply cap exceeded")]
+    SupplyCapExceeded,
+
+    #[error("asset is not transferable")]
+    NotTransferable,
+
+    #[error("asset is not redeemable")]
+    NotRedeemable,
+
+    #[error("amount overflow")]
+    Overflow,
+
+    #[error("amount has more decimal places than the asset allows")]
+    ExcessPrecision,
+}
+
+impl Transaction {
+    pub fn new(
+        command: Command,
+        nonce: u64,
+        public_key: &PublicKey,
+        private_key: &PrivateKey,
+    ) -> Result<Self, CryptoError> {
+        let signature = Ed25519Sha512::new().sign(
+            &Self::signing_bytes(&command, public_key, nonce),
+            private_key,
+        )?;
+        Ok(Self {
+            command,
+            signer_public_key: public_key.clone(),
+            nonce,
+            signature,
+        })
+    }
+
+    // Байты, которые подписывает владелец счета: команда, ключ подписанта и nonce.
+    pub fn signing_bytes(command: &Command, signer_public_key: &PublicKey, nonce: u64) -> Vec<u8> {
+        let mut out = vec![ENCODING_VERSION];
+        command.encode(&mut out);
+        signer_public_key.encode(&mut out);
+        nonce.encode(&mut out);
+        out
+    }
+
+    pub fn hash(&self) -> Hash {
+        crypto::hash_bytes(&encode_versioned(self))
+    }
+
+    // Комиссия, которую платит транзакция: сумма команды TransactionCommission.
+    pub fn fee(&self) -> Decimal {
+        match &self.command {
+            Command::TransactionCommission { value, .. } => *value,
+            _ => Decimal::default(),
+        }
+    }
+
+    pub fn verify_signature(&self) -> bool {
+        Ed25519Sha512::new()
+            .verify(
+                &Self::signing_bytes(&self.command, &self.signer_public_key, self.nonce),
+                &self.signature,
+                &self.signer_public_key,
+            )
+            .unwrap_or(false)
+    }
+
+    // Проверяет подпись и nonce, затем выполняет команду от имени подписанта.
+    // Создание счета допускается только с nonce 0.
+    pub fn execute(&self, state: &mut State, params: &Params) -> Result<(), BlockchainError> {
+        if !self.verify_signature() {
+            return Err(BlockchainError::InvalidSignature);
+        }
+        let account_id = self.command.account_id();
+        match account_id {
+            None => {
+                if self.nonce != 0 {
+                    return Err(BlockchainError::InvalidNonce {
+                        expected: 0,
+                        got: self.nonce,
+                    });
+                }
+            }
+            Some(account_id) => {
+                let account = state
+                    .accounts
+                    .get(&account_id)
+                    .ok_or(BlockchainError::AccountNotFound)?;
+                if self.nonce != account.nonce + 1 {
+                    return Err(BlockchainError::InvalidNonce {
+                        expected: account.nonce + 1,
+                        got: self.nonce,
+                    });
+                }
+            }
+        }
+        let context = ExecutionContext {
+            signer: self.signer_public_key.to_string(),
+            treasury: params.treasury,
+        };
+        self.command.execute(&context, state)?;
+        if let Some(account) = account_id.and_then(|id| state.accounts.get_mut(&id)) {
+            account.nonce = self.nonce;
+        }
+        Ok(())
+    }
+}
+
+// Контекст выполнения команды: аутентифицированный подписант транзакции
+// и счет казначейства из параметров сети.
+#[derive(Debug, Clone)]
+pub struct ExecutionContext {
+    pub signer: String,
+    pub treasury: Option<u32>,
+}
+
+impl ExecutionContext {
+    // Проверяет, что счет существует и принадлежит подписанту.
+    fn authorize(&self, accounts: &Accounts, account_id: u32) -> Result<(), BlockchainError> {
+        let account = accounts
+            .get(&account_id)
+            .ok_or(BlockchainError::AccountNotFound)?;
+        if account.public_key != self.signer {
+            return Err(BlockchainError::Unauthorized);
+        }
+        Ok(())
+    }
 }
 
 impl Command {
+    // Байты, которые подписывает ключ валидатора в команде Bond: метка и счет,
+    // регистрирующий ключ, чтобы подпись нельзя было перенести на другой счет.
+    pub fn bond_signing_bytes(account_id: u32) -> Vec<u8> {
+        let mut out = vec![ENCODING_VERSION];
+        "bond".to_string().encode(&mut out);
+        account_id.encode(&mut out);
+        out
+    }
+
+    // Счет, от имени которого выполняется команда. None для создания счета.
+    pub fn account_id(&self) -> Option<u32> {
+        match self {
+            Self::CreateAccount { .. } | Self::ReportEquivocation { .. } => None,
+            Self::AddFunds { account_id, .. }
+            | Self::UpdateAccount { account_id, .. }
+            | Self::ExecuteSmartContract { account_id, .. }
+            | Self::IssueAsset { account_id, .. }
+            | Self::RedeemAsset { account_id, .. }
+            | Self::TransactionCommission { account_id, .. }
+            | Self::Bond { account_id, .. }
+            | Self::Unbond { account_id, .. }
+            | Self::Delegate { account_id, .. } => Some(*account_id),
+            Self::RegisterAsset { definition, .. } => Some(definition.issuer_id),
+            Self::TransferFunds {
+                from_account_id, ..
+            }
+            | Self::TransferAsset {
+                from_account_id, ..
+            } => Some(*from_account_id),
+        }
+    }
+
+    // Запись актива (счет, актив), которую команда изменяет на счете отправителя.
+    pub fn holding(&self) -> Option<(u32, String)> {
+        match self {
+            Self::CreateAccount { .. }
+            | Self::UpdateAccount { .. }
+            | Self::ExecuteSmartContract { .. }
+            | Self::RegisterAsset { .. }
+            | Self::ReportEquivocation { .. } => None,
+            Self::AddFunds {
+                account_id,
+                asset_id,
+                ..
+            }
+            | Self::IssueAsset {
+                account_id,
+                asset_id,
+                ..
+            }
+            | Self::RedeemAsset {
+                account_id,
+                asset_id,
+                ..
+            } => Some((*account_id, asset_id.clone())),
+            Self::TransferFunds {
+                from_account_id,
+                asset_id,
+                ..
+            }
+            | Self::TransferAsset {
+                from_account_id,
+                asset_id,
+                ..
+            } => Some((*from_account_id, asset_id.clone())),
+            Self::TransactionCommission { account_id, .. }
+            | Self::Bond { account_id, .. }
+            | Self::Unbond { account_id, .. }
+            | Self::Delegate { account_id, .. } => Some((*account_id, "currency".to_string())),
+        }
+    }
+
     pub fn execute(
         &self,
-        accounts: &mut Accounts,
-        assets: &mut Assets,
+        context: &ExecutionContext,
+        state: &mut State,
     ) -> Result<(), BlockchainError> {
         match self {
-            // Добавляет новый счет в Accounts
+            // Добавляет новый счет в Accounts. Счет можно создать только для собственного ключа.
             Self::CreateAccount { public_key } => {
-                accounts.insert(
-                    (accounts.len() + 1) as u32,
+                if *public_key != context.signer {
+                    return Err(BlockchainError::Unauthorized);
+                }
+                if state
+                    .accounts
+                    .values()
+                    .any(|account| account.public_key == *public_key)
+                {
+                    return Err(BlockchainError::AccountExists);
+                }
+                state.accounts.insert(
+                    (state.accounts.len() + 1) as u32,
                     Account {
                         public_key: public_key.clone(),
                         name: None,
                         contact_info: None,
                         balance: 0,
+                        nonce: 0,
                     },
                 );
             }
-            // Добавляет указанную сумму средств на счет.
+            // Выпускает указанную сумму актива на собственный счет, прибавляя ее к остатку.
+            // Выпускать может только эмитент актива или казначейство; каждый выпуск
+            // записывается в журнал выпусков.
             Self::AddFunds {
                 account_id,
                 value,
                 asset_id,
             } => {
-                assets.insert(
-                    (*account_id, asset_id.clone()),
-                    Asset {
-                        value: *value,
-                        owner_id: 0,
-                    },
-                );
+                context.authorize(&state.accounts, *account_id)?;
+                let is_issuer = state.definition(asset_id)?.issuer_id == *account_id;
+                if !is_issuer && context.treasury != Some(*account_id) {
+                    return Err(BlockchainError::Unauthorized);
+                }
+                let value = state.units(asset_id, *value)?;
+                state.mint(*account_id, asset_id, value)?;
             }
             // Переводит определенную сумму средств с одного счета на другой.
+            // Запись актива получателя создается при первом переводе.
             Self::TransferFunds {
                 from_account_id,
                 to_account_id,
                 value,
                 asset_id,
             } => {
-                let asset_from = assets
-                    .get_mut(&(*from_account_id, asset_id.clone()))
-                    .ok_or(BlockchainError::AssetNotFound)?;
-                if asset_from.value < *value {
-                    return Err(BlockchainError::InsufficientBalance);
+                context.authorize(&state.accounts, *from_account_id)?;
+                if !state.definition(asset_id)?.transferable {
+                    return Err(BlockchainError::NotTransferable);
                 }
-                asset_from.value -= *value;
-
-                let asset_to = assets
-                    .get_mut(&(*to_account_id, asset_id.clone()))
-                    .ok_or(BlockchainError::AssetNotFound)?;
-                asset_to.value += *value;
+                if value.is_zero() {
+                    return Err(BlockchainError::InvalidAmount);
+                }
+                let value = state.units(asset_id, *value)?;
+                if !state.accounts.contains_key(to_account_id) {
+                    return Err(BlockchainError::AccountNotFound);
+                }
+                state.debit(*from_account_id, asset_id, value)?;
+                state.credit(*to_account_id, asset_id, value)?;
             }
             // Обновляет имя и/или контактную информацию счета.
             Self::UpdateAccount {
@@ -158,7 +548,9 @@ impl Command {
                 name,
                 contact_info,
             } => {
-                let account = accounts
+                context.authorize(&state.accounts, *account_id)?;
+                let account = state
+                    .accounts
                     .get_mut(account_id)
                     .ok_or(BlockchainError::AccountNotFound)?;
                 if let Some(name) = name {
@@ -174,12 +566,13 @@ impl Command {
                 params,
                 account_id,
             } => {
+                context.authorize(&state.accounts, *account_id)?;
                 if contract_id == "transfer_funds" {
                     if let (Some(to_account_id), Some(value), Some(asset_id)) = (
                         params
                             .get("to_account_id")
                             .and_then(|s| s.parse::<u32>().ok()),
-                        params.get("value").and_then(|s| s.parse::<i32>().ok()),
+                        params.get("value").and_then(|s| s.parse::<Decimal>().ok()),
                         params.get("asset_id"),
                     ) {
                         Command::TransferFunds {
@@ -188,7 +581,7 @@ impl Command {
                             value,
                             asset_id: asset_id.clone(),
                         }
-                        .execute(accounts, assets)?;
+                        .execute(context, state)?;
                     } else {
                         return Err(BlockchainError::FailedToParseParameters);
                     }
@@ -196,19 +589,19 @@ impl Command {
                     return Err(BlockchainError::UnknownContract(contract_id.clone()));
                 }
             }
-            // добавляет на счет определенное количество определенного актива.
+            // добавляет на счет эмитента определенное количество зарегистрированного актива;
+            // выпуск записывается в журнал выпусков, как и у AddFunds.
             Self::IssueAsset {
                 account_id,
                 asset_id,
                 value,
             } => {
-                let asset = assets
-                    .entry((*account_id, asset_id.clone()))
-                    .or_insert(Asset {
-                        value: 0,
-                        owner_id: *account_id,
-                    });
-                asset.value += *value;
+                context.authorize(&state.accounts, *account_id)?;
+                if state.definition(asset_id)?.issuer_id != *account_id {
+                    return Err(BlockchainError::Unauthorized);
+                }
+                let value = state.units(asset_id, *value)?;
+                state.mint(*account_id, asset_id, value)?;
             }
             // передает право собственности на указанный актив с одного счета на другой.
             Self::TransferAsset {
@@ -216,42 +609,1111 @@ impl Command {
                 to_account_id,
                 asset_id,
             } => {
-                let asset = assets
+                context.authorize(&state.accounts, *from_account_id)?;
+                if !state.definition(asset_id)?.transferable {
+                    return Err(BlockchainError::NotTransferable);
+                }
+                let asset = state
+                    .assets
                     .get_mut(&(*from_account_id, asset_id.clone()))
                     .ok_or(BlockchainError::AssetNotFound)?;
                 asset.owner_id = *to_account_id;
             }
             // уменьшает количество указанного актива на счете и увеличивает количество другого указанного актива на ту же сумму.
+            // Сумма переводится в единицы каждого актива по его числу знаков.
+            // Обмен выпускает целевой актив, поэтому подписать его может только эмитент целевого актива или казначейство.
             Self::RedeemAsset {
                 account_id,
                 asset_id,
                 value,
                 redeem_in_asset_id,
             } => {
-                let asset = assets
-                    .get_mut(&(*account_id, asset_id.clone()))
-                    .ok_or(BlockchainError::AssetNotFound)?;
-                if asset.value < *value {
-                    return Err(BlockchainError::InsufficientBalance);
+                context.authorize(&state.accounts, *account_id)?;
+                if !state.definition(asset_id)?.redeemable {
+                    return Err(BlockchainError::NotRedeemable);
                 }
-                asset.value -= *value;
-
-                let redeem_in_asset = assets
-                    .get_mut(&(*account_id, redeem_in_asset_id.clone()))
-                    .ok_or(BlockchainError::AssetNotFound)?;
-                redeem_in_asset.value += *value;
+                if state.definition(redeem_in_asset_id)?.issuer_id != *account_id
+                    && context.treasury != Some(*account_id)
+                {
+                    return Err(BlockchainError::Unauthorized);
+                }
+                if value.is_zero() {
+                    return Err(BlockchainError::InvalidAmount);
+                }
+                let (redeemed, received) = (
+                    state.units(asset_id, *value)?,
+                    state.units(redeem_in_asset_id, *value)?,
+                );
+                state.check_supply_cap(redeem_in_asset_id, received)?;
+                if !state
+                    .assets
+                    .contains_key(&(*account_id, redeem_in_asset_id.clone()))
+                {
+                    return Err(BlockchainError::AssetNotFound);
+                }
+                state.debit(*account_id, asset_id, redeemed)?;
+                state.credit(*account_id, redeem_in_asset_id, received)?;
+                state.supply_mut(asset_id).burn(redeemed)?;
+                state.supply_mut(redeem_in_asset_id).issue(received)?;
             }
             // Вычитает указанную сумму средств со счета в качестве комиссии за транзакцию.
+            // Комиссии блока зачисляются автору блока в конце блока.
             Self::TransactionCommission { account_id, value } => {
-                let asset = assets
-                    .get_mut(&(*account_id, "currency".to_string()))
-                    .ok_or(BlockchainError::AssetNotFound)?;
-                if asset.value < *value {
-                    return Err(BlockchainError::InsufficientBalance);
+                context.authorize(&state.accounts, *account_id)?;
+                let value = state.units("currency", *value)?;
+                state.debit(*account_id, "currency", value)?;
+            }
+            // Регистрирует актив в реестре. Идентификатор актива занимается навсегда.
+            Self::RegisterAsset {
+                asset_id,
+                definition,
+            } => {
+                context.authorize(&state.accounts, definition.issuer_id)?;
+                if state.definitions.contains_key(asset_id) {
+                    return Err(BlockchainError::AssetExists);
+                }
+                if definition.decimals > MAX_DECIMALS {
+                    return Err(BlockchainError::ExcessPrecision);
+                }
+                state
+                    .definitions
+                    .insert(asset_id.clone(), definition.clone());
+            }
+            // Ключ валидатора можно сменить повторной регистрацией, но не занять чужой.
+            // Ключ хранится в каноническом виде (hex в нижнем регистре), чтобы один ключ
+            // нельзя было занять дважды, записав его в другом регистре.
+            Self::Bond {
+                account_id,
+                public_key,
+                value,
+                key_signature,
+            } => {
+                context.authorize(&state.accounts, *account_id)?;
+                let key = parse_public_key(public_key)
+                    .map_err(|_| BlockchainError::FailedToParseParameters)?;
+                if !Ed25519Sha512::new()
+                    .verify(
+                        &Command::bond_signing_bytes(*account_id),
+                        key_signature,
+                        &key,
+                    )
+                    .unwrap_or(false)
+                {
+                    return Err(BlockchainError::InvalidSignature);
+                }
+                let public_key = to_hex(&key.0);
+                if state
+                    .staking
+                    .validator_account(&public_key)
+                    .is_some_and(|owner| owner != *account_id)
+                {
+                    return Err(BlockchainError::ValidatorKeyInUse);
+                }
+                let value = withdraw_currency(state, *account_id, *value)?;
+                state.staking.validators.insert(*account_id, public_key);
+                state.staking.bond(*account_id, *account_id, value)?;
+            }
+            // Делегирует стейк зарегистрированному валидатору.
+            Self::Delegate {
+                account_id,
+                validator_id,
+                value,
+            } => {
+                context.authorize(&state.accounts, *account_id)?;
+                if !state.staking.validators.contains_key(validator_id) {
+                    return Err(BlockchainError::ValidatorNotFound);
+                }
+                let value = withdraw_currency(state, *account_id, *value)?;
+                state.staking.bond(*account_id, *validator_id, value)?;
+            }
+            // Выведенный стейк возвращается на счет в конце следующей эпохи.
+            Self::Unbond {
+                account_id,
+                validator_id,
+                value,
+            } => {
+                context.authorize(&state.accounts, *account_id)?;
+                if value.is_zero() {
+                    return Err(BlockchainError::InvalidAmount);
+                }
+                let value = state.units("currency", *value)?;
+                state.staking.unbond(*account_id, *validator_id, value)?;
+            }
+            // Сжигает весь стейк, поставленный за валидатора с двойной подписью,
+            // и сразу исключает его из набора валидаторов.
+            Self::ReportEquivocation { evidence } => {
+                if !evidence.verify() {
+                    return Err(BlockchainError::InvalidEvidence);
                 }
-                asset.value -= *value;
+                let public_key = to_hex(&evidence.first.signer_public_key.0);
+                let header = &evidence.first.header;
+                state
+                    .staking
+                    .record_evidence(&public_key, header.height, header.round)?;
+                let validator_id = state
+                    .staking
+                    .validator_account(&public_key)
+                    .ok_or(BlockchainError::ValidatorNotFound)?;
+                let slashed = state.staking.slash(validator_id)?;
+                state.supply_mut("currency").burn(slashed)?;
             }
         }
         Ok(())
     }
 }
+
+// Списывает положительную сумму с актива "currency" счета для стейка
+// и возвращает ее в наименьших единицах.
+fn withdraw_currency(
+    state: &mut State,
+    account_id: u32,
+    value: Decimal,
+) -> Result<Amount, BlockchainError> {
+    if value.is_zero() {
+        return Err(BlockchainError::InvalidAmount);
+    }
+    let value = state.units("currency", value)?;
+    state.debit(account_id, "currency", value)?;
+    Ok(value)
+}
+
+impl Supply {
+    pub fn total(&self) -> Amount {
+        self.issued.saturating_sub(self.burned)
+    }
+
+    fn issue(&mut self, value: Amount) -> Result<(), BlockchainError> {
+        self.issued = self
+            .issued
+            .checked_add(value)
+            .ok_or(BlockchainError::Overflow)?;
+        Ok(())
+    }
+
+    fn burn(&mut self, value: Amount) -> Result<(), BlockchainError> {
+        self.burned = self
+            .burned
+            .checked_add(value)
+            .ok_or(BlockchainError::Overflow)?;
+        Ok(())
+    }
+}
+
+impl State {
+    // Начальное состояние сети. Если задано казначейство, в нем уже зарегистрирована
+    // "currency" с казначейством в роли эмитента — в ней платятся комиссии, стейк и награда.
+    pub fn genesis(params: &Params) -> Self {
+        let mut state = Self::default();
+        if let Some(treasury) = params.treasury {
+            state.definitions.insert(
+                "currency".to_string(),
+                AssetDefinition {
+                    name: "Currency".to_string(),
+                    decimals: CURRENCY_DECIMALS,
+                    issuer_id: treasury,
+                    max_supply: None,
+                    transferable: true,
+                    redeemable: false,
+                },
+            );
+        }
+        state
+    }
+
+    pub fn definition(&self, asset_id: &str) -> Result<&AssetDefinition, BlockchainError> {
+        self.definitions
+            .get(asset_id)
+            .ok_or_else(|| BlockchainError::UnknownAsset(asset_id.to_string()))
+    }
+
+    // Текущая масса актива: выпущено за вычетом сожженного.
+    pub fn total_supply(&self, asset_id: &str) -> Amount {
+        self.supply
+            .get(asset_id)
+            .map_or(Amount::ZERO, Supply::total)
+    }
+
+    // Сумма value в наименьших единицах актива; лишние знаки после запятой — ошибка.
+    pub fn units(&self, asset_id: &str, value: Decimal) -> Result<Amount, BlockchainError> {
+        value.to_units(self.definition(asset_id)?.decimals)
+    }
+
+    // Сумма в наименьших единицах актива в виде десятичного числа для отображения.
+    pub fn decimal(&self, asset_id: &str, value: Amount) -> Decimal {
+        let decimals = self.definitions.get(asset_id).map_or(0, |d| d.decimals);
+        Decimal::from_units(value, decimals)
+    }
+
+    fn supply_mut(&mut self, asset_id: &str) -> &mut Supply {
+        self.supply.entry(asset_id.to_string()).or_default()
+    }
+
+    // Проверяет, что выпуск value не превысит предельную массу актива.
+    fn check_supply_cap(&self, asset_id: &str, value: Amount) -> Result<(), BlockchainError> {
+        let max_supply = self.definition(asset_id)?.max_supply;
+        let total = self
+            .total_supply(asset_id)
+            .checked_add(value)
+            .ok_or(BlockchainError::Overflow)?;
+        if max_supply.is_some_and(|max| total > max) {
+            return Err(BlockchainError::SupplyCapExceeded);
+        }
+        Ok(())
+    }
+
+    // Наибольшая часть value, выпуск которой не превысит предельную массу актива.
+    fn within_supply_cap(&self, asset_id: &str, value: Amount) -> Amount {
+        match self.definitions.get(asset_id).and_then(|d| d.max_supply) {
+            Some(max_supply) => value.min(max_supply.saturating_sub(self.total_supply(asset_id))),
+            None => value,
+        }
+    }
+
+    // Прибавляет value к остатку актива на счете, создавая запись актива при необходимости.
+    fn credit(
+        &mut self,
+        account_id: u32,
+        asset_id: &str,
+        value: Amount,
+    ) -> Result<(), BlockchainError> {
+        let asset = self
+            .assets
+            .entry((account_id, asset_id.to_string()))
+            .or_insert(Asset {
+                value: Amount::ZERO,
+                owner_id: account_id,
+            });
+        asset.value = asset
+            .value
+            .checked_add(value)
+            .ok_or(BlockchainError::Overflow)?;
+        Ok(())
+    }
+
+    // Вычитает value из остатка актива на счете.
+    fn debit(
+        &mut self,
+        account_id: u32,
+        asset_id: &str,
+        value: Amount,
+    ) -> Result<(), BlockchainError> {
+        let asset = self
+            .assets
+            .get_mut(&(account_id, asset_id.to_string()))
+            .ok_or(BlockchainError::AssetNotFound)?;
+        asset.value = asset
+            .value
+            .checked_sub(value)
+            .ok_or(BlockchainError::InsufficientBalance)?;
+        Ok(())
+    }
+
+    // Зачисляет на счет новые единицы зарегистрированного актива в пределах его предельной массы.
+    // Нулевой выпуск отклоняется; каждый выпуск записывается в журнал выпусков.
+    fn mint(
+        &mut self,
+        account_id: u32,
+        asset_id: &str,
+        value: Amount,
+    ) -> Result<(), BlockchainError> {
+        if value.is_zero() {
+            return Err(BlockchainError::InvalidAmount);
+        }
+        self.check_supply_cap(asset_id, value)?;
+        self.credit(account_id, asset_id, value)?;
+        self.supply_mut(asset_id).issue(value)?;
+        self.mints.push(MintRecord {
+            account_id,
+            asset_id: asset_id.to_string(),
+            value,
+        });
+        Ok(())
+    }
+
+    // Вызывается после транзакций блока height. Автор блока получает комиссии блока
+    // и награду за блок на счет своего валидатора (или на счет со своим ключом);
+    // если такого счета нет, комиссии сгорают, а награда не выпускается. Награда
+    // выпускается только в пределах предельной массы "currency".
+    // В конце эпохи стейк, выведенный в прошлой эпохе, возвращается на счета, а набор
+    // валидаторов пересчитывается.
+    pub fn end_block(
+        &mut self,
+        height: u64,
+        proposer: &PublicKey,
+        fees: Amount,
+        block_reward: Decimal,
+    ) -> Result<(), BlockchainError> {
+        match self.proposer_account(proposer) {
+            Some(account_id) => {
+                // Пока "currency" не зарегистрирована, награда не выпускается.
+                let block_reward = match self.units("currency", block_reward) {
+                    Ok(reward) => self.within_supply_cap("currency", reward),
+                    Err(BlockchainError::UnknownAsset(_)) => Amount::ZERO,
+                    Err(err) => return Err(err),
+                };
+                let income = fees
+                    .checked_add(block_reward)
+                    .ok_or(BlockchainError::Overflow)?;
+                self.credit_currency(account_id, income)?;
+                self.supply_mut("currency").issue(block_reward)?;
+            }
+            None => self.supply_mut("currency").burn(fees)?,
+        }
+        if !is_epoch_end(height) {
+            return Ok(());
+        }
+        for (account_id, value) in self.staking.end_epoch()? {
+            self.credit_currency(account_id, value)?;
+        }
+        Ok(())
+    }
+
+    fn proposer_account(&self, proposer: &PublicKey) -> Option<u32> {
+        self.staking
+            .validator_account(&to_hex(&proposer.0))
+            .or_else(|| {
+                let public_key = proposer.to_string();
+                self.accounts
+                    .iter()
+                    .find(|(_, account)| account.public_key == public_key)
+                    .map(|(account_id, _)| *account_id)
+            })
+    }
+
+    fn credit_currency(&mut self, account_id: u32, value: Amount) -> Result<(), BlockchainError> {
+        if value.is_zero() {
+            return Ok(());
+        }
+        self.credit(account_id, "currency", value)
+    }
+}
+
+// Текущее время в миллисекундах от начала эпохи Unix для поля timestamp заголовка.
+pub fn current_timestamp() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_millis() as u64)
+        .unwrap_or(0)
+}
+
+impl Encode for Account {
+    fn encode(&self, out: &mut Vec<u8>) {
+        self.public_key.encode(out);
+        self.name.encode(out);
+        self.contact_info.encode(out);
+        self.balance.encode(out);
+        self.nonce.encode(out);
+    }
+}
+
+impl Decode for Account {
+    fn decode(reader: &mut Reader) -> Result<Self, DecodeError> {
+        Ok(Account {
+            public_key: Decode::decode(reader)?,
+            name: Decode::decode(reader)?,
+            contact_info: Decode::decode(reader)?,
+            balance: Decode::decode(reader)?,
+            nonce: Decode::decode(reader)?,
+        })
+    }
+}
+
+impl Encode for Asset {
+    fn encode(&self, out: &mut Vec<u8>) {
+        self.value.encode(out);
+        self.owner_id.encode(out);
+    }
+}
+
+impl Decode for Asset {
+    fn decode(reader: &mut Reader) -> Result<Self, DecodeError> {
+        Ok(Asset {
+            value: Decode::decode(reader)?,
+            owner_id: Decode::decode(reader)?,
+        })
+    }
+}
+
+// Каноническая кодировка состояния мира: счета и активы, упорядоченные по ключу, и стейкинг.
+impl Encode for State {
+    fn encode(&self, out: &mut Vec<u8>) {
+        let accounts: BTreeMap<_, _> = self.accounts.iter().collect();
+        (accounts.len() as u32).encode(out);
+        for (id, account) in accounts {
+            id.encode(out);
+            account.encode(out);
+        }
+        let assets: BTreeMap<_, _> = self.assets.iter().collect();
+        (assets.len() as u32).encode(out);
+        for ((account_id, asset_id), asset) in assets {
+            account_id.encode(out);
+            asset_id.encode(out);
+            asset.encode(out);
+        }
+        self.staking.encode(out);
+        self.supply.encode(out);
+        self.definitions.encode(out);
+        self.mints.encode(out);
+    }
+}
+
+impl Encode for AssetDefinition {
+    fn encode(&self, out: &mut Vec<u8>) {
+        self.name.encode(out);
+        self.decimals.encode(out);
+        self.issuer_id.encode(out);
+        self.max_supply.encode(out);
+        self.transferable.encode(out);
+        self.redeemable.encode(out);
+    }
+}
+
+impl Decode for AssetDefinition {
+    fn decode(reader: &mut Reader) -> Result<Self, DecodeError> {
+        Ok(AssetDefinition {
+            name: Decode::decode(reader)?,
+            decimals: Decode::decode(reader)?,
+            issuer_id: Decode::decode(reader)?,
+            max_supply: Decode::decode(reader)?,
+            transferable: Decode::decode(reader)?,
+            redeemable: Decode::decode(reader)?,
+        })
+    }
+}
+
+impl Encode for MintRecord {
+    fn encode(&self, out: &mut Vec<u8>) {
+        self.account_id.encode(out);
+        self.asset_id.encode(out);
+        self.value.encode(out);
+    }
+}
+
+impl Decode for MintRecord {
+    fn decode(reader: &mut Reader) -> Result<Self, DecodeError> {
+        Ok(MintRecord {
+            account_id: Decode::decode(reader)?,
+            asset_id: Decode::decode(reader)?,
+            value: Decode::decode(reader)?,
+        })
+    }
+}
+
+impl Encode for Supply {
+    fn encode(&self, out: &mut Vec<u8>) {
+        self.issued.encode(out);
+        self.burned.encode(out);
+    }
+}
+
+impl Decode for Supply {
+    fn decode(reader: &mut Reader) -> Result<Self, DecodeError> {
+        Ok(Supply {
+            issued: Decode::decode(reader)?,
+            burned: Decode::decode(reader)?,
+        })
+    }
+}
+
+impl Decode for State {
+    fn decode(reader: &mut Reader) -> Result<Self, DecodeError> {
+        let mut accounts = HashMap::new();
+        for _ in 0..u32::decode(reader)? {
+            let id = u32::decode(reader)?;
+            accounts.insert(id, Account::decode(reader)?);
+        }
+        let mut assets = HashMap::new();
+        for _ in 0..u32::decode(reader)? {
+            let account_id = u32::decode(reader)?;
+            let asset_id = String::decode(reader)?;
+            assets.insert((account_id, asset_id), Asset::decode(reader)?);
+        }
+        Ok(State {
+            accounts,
+            assets,
+            staking: Decode::decode(reader)?,
+            supply: Decode::decode(reader)?,
+            definitions: Decode::decode(reader)?,
+            mints: Decode::decode(reader)?,
+        })
+    }
+}
+
+impl Encode for Transaction {
+    fn encode(&self, out: &mut Vec<u8>) {
+        self.command.encode(out);
+        self.signer_public_key.encode(out);
+        self.nonce.encode(out);
+        self.signature.encode(out);
+    }
+}
+
+impl Decode for Transaction {
+    fn decode(reader: &mut Reader) -> Result<Self, DecodeError> {
+        Ok(Transaction {
+            command: Decode::decode(reader)?,
+            signer_public_key: Decode::decode(reader)?,
+            nonce: Decode::decode(reader)?,
+            signature: Decode::decode(reader)?,
+        })
+    }
+}
+
+// Теги вариантов фиксированы: новые варианты получают новые номера, старые не переиспользуются.
+impl Encode for Command {
+    fn encode(&self, out: &mut Vec<u8>) {
+        match self {
+            Self::CreateAccount { public_key } => {
+                out.push(0);
+                public_key.encode(out);
+            }
+            Self::AddFunds {
+                account_id,
+                value,
+                asset_id,
+            } => {
+                out.push(1);
+                account_id.encode(out);
+                value.encode(out);
+                asset_id.encode(out);
+            }
+            Self::TransferFunds {
+                from_account_id,
+                to_account_id,
+                value,
+                asset_id,
+            } => {
+                out.push(2);
+                from_account_id.encode(out);
+                to_account_id.encode(out);
+                value.encode(out);
+                asset_id.encode(out);
+            }
+            Self::UpdateAccount {
+                account_id,
+                name,
+                contact_info,
+            } => {
+                out.push(3);
+                account_id.encode(out);
+                name.encode(out);
+                contact_info.encode(out);
+            }
+            Self::ExecuteSmartContract {
+                contract_id,
+                params,
+                account_id,
+            } => {
+                out.push(4);
+                contract_id.encode(out);
+                params.encode(out);
+                account_id.encode(out);
+            }
+            Self::IssueAsset {
+                account_id,
+                asset_id,
+                value,
+            } => {
+                out.push(5);
+                account_id.encode(out);
+                asset_id.encode(out);
+                value.encode(out);
+            }
+            Self::TransferAsset {
+                from_account_id,
+                to_account_id,
+                asset_id,
+            } => {
+                out.push(6);
+                from_account_id.encode(out);
+                to_account_id.encode(out);
+                asset_id.encode(out);
+            }
+            Self::RedeemAsset {
+                account_id,
+                asset_id,
+                value,
+                redeem_in_asset_id,
+            } => {
+                out.push(7);
+                account_id.encode(out);
+                asset_id.encode(out);
+                value.encode(out);
+                redeem_in_asset_id.encode(out);
+            }
+            Self::TransactionCommission { account_id, value } => {
+                out.push(8);
+                account_id.encode(out);
+                value.encode(out);
+            }
+            Self::Bond {
+                account_id,
+                public_key,
+                value,
+                key_signature,
+            } => {
+                out.push(9);
+                account_id.encode(out);
+                public_key.encode(out);
+                value.encode(out);
+                key_signature.encode(out);
+            }
+            Self::Unbond {
+                account_id,
+                validator_id,
+                value,
+            } => {
+                out.push(10);
+                account_id.encode(out);
+                validator_id.encode(out);
+                value.encode(out);
+            }
+            Self::Delegate {
+                account_id,
+                validator_id,
+                value,
+            } => {
+                out.push(11);
+                account_id.encode(out);
+                validator_id.encode(out);
+                value.encode(out);
+            }
+            Self::ReportEquivocation { evidence } => {
+                out.push(12);
+                evidence.encode(out);
+            }
+            Self::RegisterAsset {
+                asset_id,
+                definition,
+            } => {
+                out.push(13);
+                asset_id.encode(out);
+                definition.encode(out);
+            }
+        }
+    }
+}
+
+impl Decode for Command {
+    fn decode(reader: &mut Reader) -> Result<Self, DecodeError> {
+        let command = match reader.read_u8()? {
+            0 => Self::CreateAccount {
+                public_key: Decode::decode(reader)?,
+            },
+            1 => Self::AddFunds {
+                account_id: Decode::decode(reader)?,
+                value: Decode::decode(reader)?,
+                asset_id: Decode::decode(reader)?,
+            },
+            2 => Self::TransferFunds {
+                from_account_id: Decode::decode(reader)?,
+                to_account_id: Decode::decode(reader)?,
+                value: Decode::decode(reader)?,
+                asset_id: Decode::decode(reader)?,
+            },
+            3 => Self::UpdateAccount {
+                account_id: Decode::decode(reader)?,
+                name: Decode::decode(reader)?,
+                contact_info: Decode::decode(reader)?,
+            },
+            4 => Self::ExecuteSmartContract {
+                contract_id: Decode::decode(reader)?,
+                params: Decode::decode(reader)?,
+                account_id: Decode::decode(reader)?,
+            },
+            5 => Self::IssueAsset {
+                account_id: Decode::decode(reader)?,
+                asset_id: Decode::decode(reader)?,
+                value: Decode::decode(reader)?,
+            },
+            6 => Self::TransferAsset {
+                from_account_id: Decode::decode(reader)?,
+                to_account_id: Decode::decode(reader)?,
+                asset_id: Decode::decode(reader)?,
+            },
+            7 => Self::RedeemAsset {
+                account_id: Decode::decode(reader)?,
+                asset_id: Decode::decode(reader)?,
+                value: Decode::decode(reader)?,
+                redeem_in_asset_id: Decode::decode(reader)?,
+            },
+            8 => Self::TransactionCommission {
+                account_id: Decode::decode(reader)?,
+                value: Decode::decode(reader)?,
+            },
+            9 => Self::Bond {
+                account_id: Decode::decode(reader)?,
+                public_key: Decode::decode(reader)?,
+                value: Decode::decode(reader)?,
+                key_signature: Decode::decode(reader)?,
+            },
+            10 => Self::Unbond {
+                account_id: Decode::decode(reader)?,
+                validator_id: Decode::decode(reader)?,
+                value: Decode::decode(reader)?,
+            },
+            11 => Self::Delegate {
+                account_id: Decode::decode(reader)?,
+                validator_id: Decode::decode(reader)?,
+                value: Decode::decode(reader)?,
+            },
+            12 => Self::ReportEquivocation {
+                evidence: Box::new(Decode::decode(reader)?),
+            },
+            13 => Self::RegisterAsset {
+                asset_id: Decode::decode(reader)?,
+                definition: Decode::decode(reader)?,
+            },
+            tag => {
+                return Err(DecodeError::InvalidTag {
+                    type_name: "Command",
+                    tag,
+                })
+            }
+        };
+        Ok(command)
+    }
+}
+
+impl Encode for BlockHeader {
+    fn encode(&self, out: &mut Vec<u8>) {
+        self.height.encode(out);
+        self.timestamp.encode(out);
+        self.proposer_id.encode(out);
+        self.round.encode(out);
+        self.previous_block_hash.encode(out);
+        self.transactions_root.encode(out);
+        self.state_root.encode(out);
+    }
+}
+
+impl Decode for BlockHeader {
+    fn decode(reader: &mut Reader) -> Result<Self, DecodeError> {
+        Ok(BlockHeader {
+            height: Decode::decode(reader)?,
+            timestamp: Decode::decode(reader)?,
+            proposer_id: Decode::decode(reader)?,
+            round: Decode::decode(reader)?,
+            previous_block_hash: Decode::decode(reader)?,
+            transactions_root: Decode::decode(reader)?,
+            state_root: Decode::decode(reader)?,
+        })
+    }
+}
+
+impl BlockHeader {
+    // Байты, которые подписывает автор блока: каноническая кодировка заголовка с версией.
+    pub fn signing_bytes(&self) -> Vec<u8> {
+        encode_versioned(self)
+    }
+}
+
+impl Encode for Block {
+    fn encode(&self, out: &mut Vec<u8>) {
+        self.header.encode(out);
+        self.data.encode(out);
+        self.signature.encode(out);
+        self.signer_public_key.encode(out);
+    }
+}
+
+impl Decode for Block {
+    fn decode(reader: &mut Reader) -> Result<Self, DecodeError> {
+        Ok(Block {
+            header: Decode::decode(reader)?,
+            data: Decode::decode(reader)?,
+            signature: Decode::decode(reader)?,
+            signer_public_key: Decode::decode(reader)?,
+        })
+    }
+}
+
+impl Encode for SignedHeader {
+    fn encode(&self, out: &mut Vec<u8>) {
+        self.header.encode(out);
+        self.signature.encode(out);
+        self.signer_public_key.encode(out);
+    }
+}
+
+impl Decode for SignedHeader {
+    fn decode(reader: &mut Reader) -> Result<Self, DecodeError> {
+        Ok(SignedHeader {
+            header: Decode::decode(reader)?,
+            signature: Decode::decode(reader)?,
+            signer_public_key: Decode::decode(reader)?,
+        })
+    }
+}
+
+impl Encode for Equivocation {
+    fn encode(&self, out: &mut Vec<u8>) {
+        self.first.encode(out);
+        self.second.encode(out);
+    }
+}
+
+impl Decode for Equivocation {
+    fn decode(reader: &mut Reader) -> Result<Self, DecodeError> {
+        Ok(Equivocation {
+            first: Decode::decode(reader)?,
+            second: Decode::decode(reader)?,
+        })
+    }
+}
+
+impl SignedHeader {
+    pub fn verify_signature(&self) -> bool {
+        Ed25519Sha512::new()
+            .verify(
+                &self.header.signing_bytes(),
+                &self.signature,
+                &self.signer_public_key,
+            )
+            .unwrap_or(false)
+    }
+}
+
+impl Equivocation {
+    // Оба заголовка подписаны одним ключом, относятся к одной высоте и раунду и различаются.
+    pub fn verify(&self) -> bool {
+        let (first, second) = (&self.first, &self.second);
+        first.signer_public_key == second.signer_public_key
+            && first.header.height == second.header.height
+            && first.header.round == second.header.round
+            && first.header.signing_bytes() != second.header.signing_bytes()
+            && first.verify_signature()
+            && second.verify_signature()
+    }
+}
+
+impl Block {
+    pub fn new(
+        header: BlockHeader,
+        data: Vec<Transaction>,
+        public_key: &PublicKey,
+        private_key: &PrivateKey,
+    ) -> Result<Self, CryptoError> {
+        let signature = Ed25519Sha512::new().sign(&header.signing_bytes(), private_key)?;
+        Ok(Self {
+            header,
+            data,
+            signature,
+            signer_public_key: public_key.clone(),
+        })
+    }
+
+    pub fn verify_signature(&self) -> bool {
+        self.signed_header().verify_signature()
+    }
+
+    pub fn signed_header(&self) -> SignedHeader {
+        SignedHeader {
+            header: self.header.clone(),
+            signature: self.signature.clone(),
+            signer_public_key: self.signer_public_key.clone(),
+        }
+    }
+
+    // Корень дерева Меркла над хэшами транзакций.
+    pub fn transactions_root(data: &[Transaction]) -> Hash {
+        let leaves: Vec<Hash> = data.iter().map(Transaction::hash).collect();
+        crypto::merkle_root(&leaves)
+    }
+
+    pub fn serialize(&self) -> Vec<u8> {
+        encode_versioned(self)
+    }
+
+    pub fn deserialize(bytes: &[u8]) -> Result<Self, DecodeError> {
+        decode_versioned(bytes)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn header(height: u64, timestamp: u64) -> BlockHeader {
+        BlockHeader {
+            height,
+            timestamp,
+            proposer_id: 1,
+            round: 0,
+            previous_block_hash: Some(vec![1; 32]),
+            transactions_root: vec![2; 32],
+            state_root: vec![3; 32],
+        }
+    }
+
+    // По одной команде каждого варианта.
+    fn commands() -> Vec<Command> {
+        let (public_key, private_key) = Ed25519Sha512::new().keypair(None).unwrap();
+        let signed = |timestamp| {
+            Block::new(header(5, timestamp), vec![], &public_key, &private_key)
+                .unwrap()
+                .signed_header()
+        };
+        let value = Decimal::new(1250, 2);
+        vec![
+            Command::CreateAccount {
+                public_key: public_key.to_string(),
+            },
+            Command::AddFunds {
+                account_id: 1,
+                value,
+                asset_id: "currency".to_string(),
+            },
+            Command::TransferFunds {
+                from_account_id: 1,
+                to_account_id: 2,
+                value,
+                asset_id: "currency".to_string(),
+            },
+            Command::UpdateAccount {
+                account_id: 1,
+                name: Some("Alice".to_string()),
+                contact_info: None,
+            },
+            Command::ExecuteSmartContract {
+                contract_id: "transfer_funds".to_string(),
+                params: [("value", "1"), ("asset_id", "currency")]
+                    .into_iter()
+                    .map(|(key, value)| (key.to_string(), value.to_string()))
+                    .collect(),
+                account_id: 1,
+            },
+            Command::IssueAsset {
+                account_id: 1,
+                asset_id: "gold".to_string(),
+                value,
+            },
+            Command::TransferAsset {
+                from_account_id: 1,
+                to_account_id: 2,
+                asset_id: "gold".to_string(),
+            },
+            Command::RedeemAsset {
+                account_id: 1,
+                asset_id: "gold".to_string(),
+                value,
+                redeem_in_asset_id: "currency".to_string(),
+            },
+            Command::TransactionCommission {
+                account_id: 1,
+                value,
+            },
+            Command::Bond {
+                account_id: 1,
+                public_key: to_hex(&public_key.0),
+                value,
+                key_signature: Ed25519Sha512::new()
+                    .sign(&Command::bond_signing_bytes(1), &private_key)
+                    .unwrap(),
+            },
+            Command::Unbond {
+                account_id: 1,
+                validator_id: 1,
+                value,
+            },
+            Command::Delegate {
+                account_id: 2,
+                validator_id: 1,
+                value,
+            },
+            Command::ReportEquivocation {
+                evidence: Box::new(Equivocation {
+                    first: signed(1),
+                    second: signed(2),
+                }),
+            },
+            Command::RegisterAsset {
+                asset_id: "gold".to_string(),
+                definition: AssetDefinition {
+                    name: "Gold".to_string(),
+                    decimals: 8,
+                    issuer_id: 1,
+                    max_supply: Some(Amount::new(21_000_000)),
+                    transferable: true,
+                    redeemable: false,
+                },
+            },
+        ]
+    }
+
+    // Декодирует байты обратно и проверяет, что кодировка значения не изменилась.
+    // Кодировка каноническая, поэтому совпадение байтов означает совпадение значений.
+    fn assert_round_trip<T: Encode + Decode>(value: &T) {
+        let bytes = encode_versioned(value);
+        let decoded: T = decode_versioned(&bytes).unwrap();
+        assert_eq!(encode_versioned(&decoded), bytes);
+    }
+
+    #[test]
+    // проверяет кодирование и декодирование каждого варианта команды без потерь.
+    fn test_every_command_round_trips() {
+        let commands = commands();
+        let tags: Vec<u8> = commands
+            .iter()
+            .map(|command| encode_versioned(command)[1])
+            .collect();
+        assert_eq!(tags, (0..=13).collect::<Vec<u8>>());
+        for command in &commands {
+            assert_round_trip(command);
+        }
+    }
+
+    #[test]
+    // проверяет кодирование транзакций и блока: подписи остаются действительными после декодирования.
+    fn test_transactions_and_block_round_trip() {
+        let (public_key, private_key) = Ed25519Sha512::new().keypair(None).unwrap();
+        let data: Vec<Transaction> = commands()
+            .into_iter()
+            .enumerate()
+            .map(|(nonce, command)| {
+                Transaction::new(command, nonce as u64, &public_key, &private_key).unwrap()
+            })
+            .collect();
+        for transaction in &data {
+            assert_round_trip(transaction);
+            let decoded: Transaction = decode_versioned(&encode_versioned(transaction)).unwrap();
+            assert!(decoded.verify_signature());
+        }
+
+        let block = Block::new(header(3, 42), data, &public_key, &private_key).unwrap();
+        assert_round_trip(&block);
+        let decoded = Block::deserialize(&block.serialize()).unwrap();
+        assert!(decoded.verify_signature());
+        assert_eq!(
+            Block::transactions_root(&decoded.data),
+            Block::transactions_root(&block.data)
+        );
+    }
+
+    #[test]
+    // проверяет, что неизвестный тег команды и лишние байты после блока отклоняются.
+    fn test_unknown_tag_and_trailing_bytes_are_rejected() {
+        assert!(matches!(
+            decode_versioned::<Command>(&[ENCODING_VERSION, 14]),
+            Err(DecodeError::InvalidTag {
+                type_name: "Command",
+                tag: 14
+            })
+        ));
+        let (public_key, private_key) = Ed25519Sha512::new().keypair(None).unwrap();
+        let mut bytes = Block::new(header(0, 0), vec![], &public_key, &private_key)
+            .unwrap()
+            .serialize();
+        bytes.push(0);
+        assert!(matches!(
+            Block::deserialize(&bytes),
+            Err(DecodeError::TrailingBytes(1))
+        ));
+        bytes.pop();
+        bytes[0] = ENCODING_VERSION + 1;
+        assert!(matches!(
+            Block::deserialize(&bytes),
+            Err(DecodeError::UnsupportedVersion(_))
+        ));
+    }
+}
@@ -1,22 +1,112 @@
 use blake2::{Blake2s256, Digest};
 
-use crate::comands::Block;
+use crate::comands::{Block, State};
+use crate::state_tree::StateTree;
 
 pub type Hash = Vec<u8>;
 
+// Хэш блока вычисляется только по заголовку.
 pub fn hash(block: &Block) -> Hash {
+    hash_bytes(&block.header.signing_bytes())
+}
+
+pub fn hash_bytes(bytes: &[u8]) -> Hash {
     let mut hasher = Blake2s256::new();
-    hasher.update(format!("{:?}", block).as_bytes());
+    hasher.update(bytes);
     let res = hasher.finalize();
     let mut vector = Vec::new();
     vector.extend_from_slice(&res);
     vector
 }
-pub fn calculate_random_number(hash: Hash) -> f64 {
-    let mut array = [0u8; 8];
-    for (i, &byte) in hash.iter().enumerate().take(8) {
-        array[i] = byte;
+
+// Листья и внутренние узлы хэшируются с разными префиксами, чтобы внутренний узел
+// нельзя было выдать за лист. Непарный узел поднимается на уровень выше без изменений.
+const MERKLE_LEAF_PREFIX: u8 = 0;
+const MERKLE_NODE_PREFIX: u8 = 1;
+
+pub fn merkle_leaf(leaf: &Hash) -> Hash {
+    hash_bytes(&[&[MERKLE_LEAF_PREFIX], leaf.as_slice()].concat())
+}
+
+pub fn merkle_node(left: &Hash, right: &Hash) -> Hash {
+    hash_bytes(&[&[MERKLE_NODE_PREFIX], left.as_slice(), right.as_slice()].concat())
+}
+
+pub fn merkle_root(leaves: &[Hash]) -> Hash {
+    if leaves.is_empty() {
+        return hash_bytes(&[]);
+    }
+    let mut level: Vec<Hash> = leaves.iter().map(merkle_leaf).collect();
+    while level.len() > 1 {
+        level = level
+            .chunks(2)
+            .map(|pair| match pair {
+                [left, right] => merkle_node(left, right),
+                [single] => single.clone(),
+                _ => unreachable!(),
+            })
+            .collect();
+    }
+    level.remove(0)
+}
+
+// Шаг доказательства включения: соседний хэш и его сторона относительно текущего узла.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MerkleStep {
+    pub sibling: Hash,
+    pub sibling_is_left: bool,
+}
+
+// Доказательство включения листа в дерево Меркла: путь от листа к корню.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MerkleProof {
+    pub steps: Vec<MerkleStep>,
+}
+
+impl MerkleProof {
+    // Строит доказательство для листа с номером index. None, если такого листа нет.
+    pub fn new(leaves: &[Hash], index: usize) -> Option<Self> {
+        if index >= leaves.len() {
+            return None;
+        }
+        let mut steps = Vec::new();
+        let mut level: Vec<Hash> = leaves.iter().map(merkle_leaf).collect();
+        let mut position = index;
+        while level.len() > 1 {
+            let sibling = position ^ 1;
+            // Непарный узел поднимается без хэширования, шаг не нужен.
+            if sibling < level.len() {
+                steps.push(MerkleStep {
+                    sibling: level[sibling].clone(),
+                    sibling_is_left: sibling < position,
+                });
+            }
+            level = level
+                .chunks(2)
+                .map(|pair| match pair {
+                    [left, right] => merkle_node(left, right),
+                    [single] => single.clone(),
+                    _ => unreachable!(),
+                })
+                .collect();
+            position /= 2;
+        }
+        Some(Self { steps })
+    }
+
+    pub fn verify(&self, root: &Hash, leaf: &Hash) -> bool {
+        let computed = self.steps.iter().fold(merkle_leaf(leaf), |node, step| {
+            if step.sibling_is_left {
+                merkle_node(&step.sibling, &node)
+            } else {
+                merkle_node(&node, &step.sibling)
+            }
+        });
+        &computed == root
     }
-    let num = u64::from_be_bytes(array);
-    num as f64 / u64::MAX as f64
+}
+
+// Корень состояния: корень разреженного дерева Меркла над счетами, активами и стейкингом.
+pub fn state_root(state: &State) -> Hash {
+    StateTree::new(state).root()
 }
@@ -0,0 +1,406 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+
+use thiserror::Error;
+use ursa::keys::PublicKey;
+
+// Версия канонического формата. Меняется при любом несовместимом изменении раскладки байтов.
+pub const ENCODING_VERSION: u8 = 1;
+
+#[derive(Debug, Error)]
+pub enum DecodeError {
+    #[error("unexpected end of input")]
+    UnexpectedEof,
+
+    #[error("unsupported encoding version: {0}")]
+    UnsupportedVersion(u8),
+
+    #[error("invalid tag {tag} for {type_name}")]
+    InvalidTag { type_name: &'static str, tag: u8 },
+
+    #[error("invalid utf-8 string")]
+    InvalidUtf8,
+
+    #[error("map keys are not strictly increasing")]
+    UnsortedKeys,
+
+    #[error("invalid value for {type_name}")]
+    InvalidValue { type_name: &'static str },
+
+    #[error("trailing bytes after value: {0}")]
+    TrailingBytes(usize),
+}
+
+// Детерминированная сериализация: целые числа в big-endian, длины как u32,
+// словари сортируются по ключу. Не зависит от Debug и порядка derive.
+pub trait Encode {
+    fn encode(&self, out: &mut Vec<u8>);
+}
+
+pub trait Decode: Sized {
+    fn decode(reader: &mut Reader) -> Result<Self, DecodeError>;
+}
+
+// Кодирует значение с префиксом версии формата.
+pub fn encode_versioned<T: Encode + ?Sized>(value: &T) -> Vec<u8> {
+    let mut out = vec![ENCODING_VERSION];
+    value.encode(&mut out);
+    out
+}
+
+pub fn decode_versioned<T: Decode>(bytes: &[u8]) -> Result<T, DecodeError> {
+    let mut reader = Reader::new(bytes);
+    let version = reader.read_u8()?;
+    if version != ENCODING_VERSION {
+        return Err(DecodeError::UnsupportedVersion(version));
+    }
+    let value = T::decode(&mut reader)?;
+    if reader.remaining() != 0 {
+        return Err(DecodeError::TrailingBytes(reader.remaining()));
+    }
+    Ok(value)
+}
+
+pub struct Reader<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    pub fn new(bytes: &'a [u8]) -> Self {
+        Self { bytes, pos: 0 }
+    }
+
+    pub fn remaining(&self) -> usize {
+        self.bytes.len() - self.pos
+    }
+
+    pub fn read_bytes(&mut self, len: usize) -> Result<&'a [u8], DecodeError> {
+        if self.remaining() < len {
+            return Err(DecodeError::UnexpectedEof);
+        }
+        let slice = &self.bytes[self.pos..self.pos + len];
+        self.pos += len;
+        Ok(slice)
+    }
+
+    pub fn read_u8(&mut self) -> Result<u8, DecodeError> {
+        Ok(self.read_bytes(1)?[0])
+    }
+
+    fn read_array<const N: usize>(&mut self) -> Result<[u8; N], DecodeError> {
+        let mut array = [0u8; N];
+        array.copy_from_slice(self.read_bytes(N)?);
+        Ok(array)
+    }
+}
+
+impl Encode for u8 {
+    fn encode(&self, out: &mut Vec<u8>) {
+        out.push(*self);
+    }
+}
+
+impl Decode for u8 {
+    fn decode(reader: &mut Reader) -> Result<Self, DecodeError> {
+        reader.read_u8()
+    }
+}
+
+impl Encode for bool {
+    fn encode(&self, out: &mut Vec<u8>) {
+        out.push(*self as u8);
+    }
+}
+
+impl Decode for bool {
+    fn decode(reader: &mut Reader) -> Result<Self, DecodeError> {
+        match reader.read_u8()? {
+            0 => Ok(false),
+            1 => Ok(true),
+            tag => Err(DecodeError::InvalidTag {
+                type_name: "bool",
+                tag,
+            }),
+        }
+    }
+}
+
+macro_rules! impl_int {
+    ($($ty:ty),*) => {
+        $(
+            impl Encode for $ty {
+                fn encode(&self, out: &mut Vec<u8>) {
+                    out.extend_from_slice(&self.to_be_bytes());
+                }
+            }
+
+            impl Decode for $ty {
+                fn decode(reader: &mut Reader) -> Result<Self, DecodeError> {
+                    Ok(<$ty>::from_be_bytes(reader.read_array()?))
+                }
+            }
+        )*
+    };
+}
+
+impl_int!(u32, u64, i32, u128);
+
+fn encode_len(len: usize, out: &mut Vec<u8>) {
+    (len as u32).encode(out);
+}
+
+fn decode_len(reader: &mut Reader) -> Result<usize, DecodeError> {
+    Ok(u32::decode(reader)? as usize)
+}
+
+impl Encode for String {
+    fn encode(&self, out: &mut Vec<u8>) {
+        encode_len(self.len(), out);
+        out.extend_from_slice(self.as_bytes());
+    }
+}
+
+impl Decode for String {
+    fn decode(reader: &mut Reader) -> Result<Self, DecodeError> {
+        let len = decode_len(reader)?;
+        let bytes = reader.read_bytes(len)?;
+        String::from_utf8(bytes.to_vec()).map_err(|_| DecodeError::InvalidUtf8)
+    }
+}
+
+impl<T: Encode> Encode for [T] {
+    fn encode(&self, out: &mut Vec<u8>) {
+        encode_len(self.len(), out);
+        for item in self {
+            item.encode(out);
+        }
+    }
+}
+
+impl<T: Encode> Encode for Vec<T> {
+    fn encode(&self, out: &mut Vec<u8>) {
+        self.as_slice().encode(out);
+    }
+}
+
+impl<T: Decode> Decode for Vec<T> {
+    fn decode(reader: &mut Reader) -> Result<Self, DecodeError> {
+        let len = decode_len(reader)?;
+        // Не доверяем длине из входных данных при резервировании памяти.
+        let mut items = Vec::with_capacity(len.min(reader.remaining()));
+        for _ in 0..len {
+            items.push(T::decode(reader)?);
+        }
+        Ok(items)
+    }
+}
+
+impl<T: Encode> Encode for Option<T> {
+    fn encode(&self, out: &mut Vec<u8>) {
+        match self {
+            None => out.push(0),
+            Some(value) => {
+                out.push(1);
+                value.encode(out);
+            }
+        }
+    }
+}
+
+impl<T: Decode> Decode for Option<T> {
+    fn decode(reader: &mut Reader) -> Result<Self, DecodeError> {
+        match reader.read_u8()? {
+            0 => Ok(None),
+            1 => Ok(Some(T::decode(reader)?)),
+            tag => Err(DecodeError::InvalidTag {
+                type_name: "Option",
+                tag,
+            }),
+        }
+    }
+}
+
+// Ключи сортируются, чтобы порядок обхода HashMap не влиял на байты.
+impl Encode for HashMap<String, String> {
+    fn encode(&self, out: &mut Vec<u8>) {
+        let mut entries: Vec<_> = self.iter().collect();
+        entries.sort();
+        encode_len(entries.len(), out);
+        for (key, value) in entries {
+            key.encode(out);
+            value.encode(out);
+        }
+    }
+}
+
+// Принимается только каноническая форма: ключи строго возрастают, без повторов.
+impl Decode for HashMap<String, String> {
+    fn decode(reader: &mut Reader) -> Result<Self, DecodeError> {
+        Ok(BTreeMap::<String, String>::decode(reader)?
+            .into_iter()
+            .collect())
+    }
+}
+
+impl<K: Encode, V: Encode> Encode for BTreeMap<K, V> {
+    fn encode(&self, out: &mut Vec<u8>) {
+        encode_len(self.len(), out);
+        for (key, value) in self {
+            key.encode(out);
+            value.encode(out);
+        }
+    }
+}
+
+impl<K: Decode + Ord, V: Decode> Decode for BTreeMap<K, V> {
+    fn decode(reader: &mut Reader) -> Result<Self, DecodeError> {
+        let len = decode_len(reader)?;
+        let mut map = BTreeMap::new();
+        for _ in 0..len {
+            let key = K::decode(reader)?;
+            if map.last_key_value().is_some_and(|(last, _)| *last >= key) {
+                return Err(DecodeError::UnsortedKeys);
+            }
+            map.insert(key, V::decode(reader)?);
+        }
+        Ok(map)
+    }
+}
+
+impl<T: Encode> Encode for BTreeSet<T> {
+    fn encode(&self, out: &mut Vec<u8>) {
+        encode_len(self.len(), out);
+        for item in self {
+            item.encode(out);
+        }
+    }
+}
+
+impl<T: Decode + Ord> Decode for BTreeSet<T> {
+    fn decode(reader: &mut Reader) -> Result<Self, DecodeError> {
+        let len = decode_len(reader)?;
+        let mut set = BTreeSet::new();
+        for _ in 0..len {
+            let item = T::decode(reader)?;
+            if set.last().is_some_and(|last| *last >= item) {
+                return Err(DecodeError::UnsortedKeys);
+            }
+            set.insert(item);
+        }
+        Ok(set)
+    }
+}
+
+impl<A: Encode, B: Encode, C: Encode> Encode for (A, B, C) {
+    fn encode(&self, out: &mut Vec<u8>) {
+        self.0.encode(out);
+        self.1.encode(out);
+        self.2.encode(out);
+    }
+}
+
+impl<A: Decode, B: Decode, C: Decode> Decode for (A, B, C) {
+    fn decode(reader: &mut Reader) -> Result<Self, DecodeError> {
+        Ok((A::decode(reader)?, B::decode(reader)?, C::decode(reader)?))
+    }
+}
+
+impl<A: Encode, B: Encode> Encode for (A, B) {
+    fn encode(&self, out: &mut Vec<u8>) {
+        self.0.encode(out);
+        self.1.encode(out);
+    }
+}
+
+impl<A: Decode, B: Decode> Decode for (A, B) {
+    fn decode(reader: &mut Reader) -> Result<Self, DecodeError> {
+        Ok((A::decode(reader)?, B::decode(reader)?))
+    }
+}
+
+impl Encode for PublicKey {
+    fn encode(&self, out: &mut Vec<u8>) {
+        self.0.encode(out);
+    }
+}
+
+impl Decode for PublicKey {
+    fn decode(reader: &mut Reader) -> Result<Self, DecodeError> {
+        Ok(PublicKey(Vec::<u8>::decode(reader)?))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    // проверяет, что неизвестная версия, лишние байты и неверный тег отклоняются.
+    fn test_malformed_input_is_rejected() {
+        let mut bytes = encode_versioned(&7u32);
+        assert_eq!(decode_versioned::<u32>(&bytes).unwrap(), 7);
+
+        bytes[0] = ENCODING_VERSION + 1;
+        assert!(matches!(
+            decode_versioned::<u32>(&bytes),
+            Err(DecodeError::UnsupportedVersion(_))
+        ));
+
+        let mut bytes = encode_versioned(&7u32);
+        bytes.push(0);
+        assert!(matches!(
+            decode_versioned::<u32>(&bytes),
+            Err(DecodeError::TrailingBytes(1))
+        ));
+        assert!(matches!(
+            decode_versioned::<u32>(&bytes[..3]),
+            Err(DecodeError::UnexpectedEof)
+        ));
+        assert!(matches!(
+            decode_versioned::<Option<u32>>(&[ENCODING_VERSION, 2]),
+            Err(DecodeError::InvalidTag {
+                type_name: "Option",
+                tag: 2
+            })
+        ));
+        assert!(matches!(
+            decode_versioned::<bool>(&[ENCODING_VERSION, 2]),
+            Err(DecodeError::InvalidTag { .. })
+        ));
+    }
+
+    #[test]
+    // проверяет, что словари кодируются по возрастанию ключей и принимаются только в этом виде.
+    fn test_maps_are_canonical() {
+        let map: HashMap<String, String> = [("b", "2"), ("a", "1")]
+            .into_iter()
+            .map(|(key, value)| (key.to_string(), value.to_string()))
+            .collect();
+        let bytes = encode_versioned(&map);
+        assert_eq!(
+            decode_versioned::<HashMap<String, String>>(&bytes).unwrap(),
+            map
+        );
+
+        let encode_entries = |entries: &[(&str, &str)]| {
+            let mut out = vec![ENCODING_VERSION];
+            encode_len(entries.len(), &mut out);
+            for (key, value) in entries {
+                key.to_string().encode(&mut out);
+                value.to_string().encode(&mut out);
+            }
+            out
+        };
+        for entries in [[("b", "2"), ("a", "1")], [("a", "1"), ("a", "2")]] {
+            let bytes = encode_entries(&entries);
+            assert!(matches!(
+                decode_versioned::<HashMap<String, String>>(&bytes),
+                Err(DecodeError::UnsortedKeys)
+            ));
+            assert!(matches!(
+                decode_versioned::<BTreeMap<String, String>>(&bytes),
+                Err(DecodeError::UnsortedKeys)
+            ));
+        }
+    }
+}
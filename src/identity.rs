@@ -0,0 +1,143 @@
+use std::fmt;
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::path::Path;
+
+use thiserror::Error;
+use ursa::keys::{KeyGenOption, PrivateKey, PublicKey};
+use ursa::signatures::{prelude::Ed25519Sha512, SignatureScheme};
+
+use crate::comands::Command;
+use crate::encoding::{decode_versioned, encode_versioned};
+
+#[derive(Debug, Error)]
+pub enum IdentityError {
+    #[error("io error: {0}")]
+    Io(#[from] io::Error),
+
+    #[error("invalid key file: {0}")]
+    InvalidKeyFile(String),
+
+    #[error("invalid public key: {0}")]
+    InvalidPublicKey(String),
+}
+
+// Долговременная пара ключей пира. Ею подписываются блоки пира, а открытый ключ
+// указывается в наборе валидаторов других пиров.
+#[derive(Clone)]
+pub struct Identity {
+    pub public_key: PublicKey,
+    pub private_key: PrivateKey,
+}
+
+impl Identity {
+    pub fn generate() -> Self {
+        let (public_key, private_key) = Ed25519Sha512::new().keypair(None).unwrap();
+        Self {
+            public_key,
+            private_key,
+        }
+    }
+
+    pub fn from_private_key(private_key: PrivateKey) -> Result<Self, IdentityError> {
+        let (public_key, private_key) = Ed25519Sha512::new()
+            .keypair(Some(KeyGenOption::FromSecretKey(private_key)))
+            .map_err(|err| IdentityError::InvalidKeyFile(err.to_string()))?;
+        Ok(Self {
+            public_key,
+            private_key,
+        })
+    }
+
+    // Подпись для команды Bond: подтверждает, что счет account_id регистрирует
+    // валидатором именно этот ключ с согласия его владельца.
+    pub fn sign_bond(&self, account_id: u32) -> Vec<u8> {
+        Ed25519Sha512::new()
+            .sign(&Command::bond_signing_bytes(account_id), &self.private_key)
+            .unwrap()
+    }
+
+    // Файл ключа содержит закрытый ключ в канонической кодировке с версией.
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, IdentityError> {
+        let bytes = fs::read(path)?;
+        let private_key: Vec<u8> = decode_versioned(&bytes)
+            .map_err(|err| IdentityError::InvalidKeyFile(err.to_string()))?;
+        Self::from_private_key(PrivateKey(private_key))
+    }
+
+    // Файл создается заново и доступен только владельцу.
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<(), IdentityError> {
+        let path = path.as_ref();
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let mut options = OpenOptions::new();
+        options.write(true).create_new(true);
+        #[cfg(unix)]
+        std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+        let mut file = options.open(path)?;
+        file.write_all(&encode_versioned(&self.private_key.0))?;
+        file.sync_all()?;
+        Ok(())
+    }
+
+    // Загружает ключ из файла, а если файла нет — создает новый и сохраняет его.
+    pub fn load_or_generate(path: impl AsRef<Path>) -> Result<Self, IdentityError> {
+        let path = path.as_ref();
+        if path.exists() {
+            return Self::load(path);
+        }
+        let identity = Self::generate();
+        identity.save(path)?;
+        Ok(identity)
+    }
+}
+
+// Закрытый ключ не выводится в отладочный вывод.
+impl fmt::Debug for Identity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Identity")
+            .field("public_key", &to_hex(&self.public_key.0))
+            .finish()
+    }
+}
+
+pub fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+pub fn parse_public_key(hex: &str) -> Result<PublicKey, IdentityError> {
+    let invalid = || IdentityError::InvalidPublicKey(hex.to_string());
+    if hex.len() != 64 || !hex.is_ascii() {
+        return Err(invalid());
+    }
+    let bytes = (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).map_err(|_| invalid()))
+        .collect::<Result<Vec<u8>, _>>()?;
+    Ok(PublicKey(bytes))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    // проверяет, что ключ из файла совпадает с сохраненным, а файл не перезаписывается.
+    fn test_identity_is_persisted() {
+        let dir = std::env::temp_dir().join(format!("identity-test-{}", std::process::id()));
+        let path = dir.join("identity.key");
+        let _ = fs::remove_dir_all(&dir);
+
+        let created = Identity::load_or_generate(&path).unwrap();
+        let loaded = Identity::load_or_generate(&path).unwrap();
+        assert_eq!(created.public_key, loaded.public_key);
+        assert!(created.save(&path).is_err());
+        assert_eq!(
+            parse_public_key(&to_hex(&created.public_key.0)).unwrap(),
+            created.public_key
+        );
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
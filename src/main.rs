@@ -1,24 +1,37 @@
-use self::crypto::Hash;
+use crate::amount::Decimal;
 use crate::client::Client;
+use crate::comands::Params;
+use crate::identity::{to_hex, Identity};
+use crate::network::{InMemoryNetwork, Message, TcpTransport, Transport};
 use crate::peer::Peer;
 
 use std::io;
+use std::net::SocketAddr;
+use std::path::PathBuf;
 // use std::sync::mpsc::channel;
-use std::sync::{Arc, Mutex};
+use std::sync::Arc;
 use std::{
     // collections::HashMap,
-    sync::mpsc::{self},
+    sync::mpsc::{self, Sender},
     thread,
 };
-// use storage::Storage;
-use ursa::signatures::{prelude::Ed25519Sha512, SignatureScheme};
+use storage::Storage;
+use validators::{Validator, ValidatorSet};
+mod amount;
 mod client;
 mod comands;
 mod crypto;
+mod encoding;
+mod identity;
+mod mempool;
+mod network;
 mod peer;
+mod staking;
+mod state_tree;
 mod storage;
+mod validators;
 
-pub fn run_cli(client: Arc<Mutex<Client>>) {
+pub fn run_cli(client: Arc<Client>) {
     let stdin = io::stdin();
 
     loop {
@@ -33,25 +46,31 @@ pub fn run_cli(client: Arc<Mutex<Client>>) {
             };
 
             match command.as_str() {
-                "create account" => match client.lock().unwrap().create_account() {
+                "create account" => match client.create_account() {
                     Ok(()) => println!("Account created."),
                     Err(err) => println!("Error: {:?}", err),
                 },
                 "add funds" => {
-                    if command_parts.len() < 4 {
+                    if command_parts.len() != 5 {
                         println!("Usage: add funds <account_id> <value> <asset_id>");
                     } else {
-                        let account_id = command_parts[2].parse().unwrap();
-                        let value = command_parts[3].parse().unwrap();
                         let asset_id = command_parts[4].to_string();
 
-                        match client
-                            .lock()
-                            .unwrap()
-                            .add_funds(account_id, value, asset_id)
-                        {
-                            Ok(()) => println!("Funds added."),
-                            Err(err) => println!("Error: {:?}", err),
+                        // Число знаков после запятой проверяется по описанию актива при выполнении.
+                        match (
+                            command_parts[2].parse::<u32>(),
+                            command_parts[3].parse::<Decimal>(),
+                        ) {
+                            (Ok(account_id), Ok(value)) => {
+                                match client.add_funds(account_id, value, asset_id) {
+                                    Ok(()) => println!("Funds added."),
+                                    Err(err) => println!("Error: {:?}", err),
+                                }
+                            }
+                            (Err(err), _) => {
+                                println!("Error: invalid account id {}: {}", command_parts[2], err)
+                            }
+                            (_, Err(err)) => println!("Error: {}", err),
                         }
                     }
                 }
@@ -62,7 +81,9 @@ pub fn run_cli(client: Arc<Mutex<Client>>) {
                 "help" => {
                     println!("Available commands:");
                     println!("create account: Create a new account.");
-                    println!("add funds <account_id> <value> <asset_id>: Add funds to an account.");
+                    println!(
+                        "add funds <account_id> <value> <asset_id>: Mint an asset to your account (issuer or treasury only). The value is a decimal such as 12.50."
+                    );
                     println!("exit: Exit the program.");
                 }
                 _ => println!("Unknown command. Type 'help' for a list of commands."),
@@ -73,40 +94,150 @@ pub fn run_cli(client: Arc<Mutex<Client>>) {
     }
 }
 
+// Параметры запуска пира: --id <номер> --listen <адрес> --peer <адрес>...
+// --key <файл ключа> --validator <ключ в hex>:<стейк>... --block-reward <сумма>
+// --treasury <номер счета>
+// Без --listen пир работает только с клиентом внутри процесса. --validator задает
+// начальный набор валидаторов (без него — сам пир); он действует, пока в стейкинг
+// не перешли его валидаторы с кворумом стейка. --block-reward (в "currency", например 1.50) и --treasury —
+// параметры сети, одинаковые у всех пиров. С --treasury "currency" зарегистрирована с начала
+// цепочки, и казначейство выпускает ее командой add funds.
+// Ключ клиента хранится в client.key в каталоге пира, поэтому счет клиента сохраняется между запусками.
+struct Args {
+    id: u8,
+    listen: Option<SocketAddr>,
+    peers: Vec<SocketAddr>,
+    key: Option<PathBuf>,
+    validators: Vec<Validator>,
+    params: Params,
+}
+
+fn parse_args() -> Args {
+    let mut args = Args {
+        id: 1,
+        listen: None,
+        peers: Vec::new(),
+        key: None,
+        validators: Vec::new(),
+        params: Params::default(),
+    };
+    let mut iter = std::env::args().skip(1);
+    while let Some(arg) = iter.next() {
+        let value = iter
+            .next()
+            .unwrap_or_else(|| panic!("Missing value for {}", arg));
+        match arg.as_str() {
+            "--id" => args.id = value.parse().expect("Invalid peer id"),
+            "--listen" => args.listen = Some(value.parse().expect("Invalid listen address")),
+            "--peer" => args
+                .peers
+                .push(value.parse().expect("Invalid peer address")),
+            "--key" => args.key = Some(PathBuf::from(value)),
+            "--validator" => args
+                .validators
+                .push(value.parse().expect("Invalid validator")),
+            "--block-reward" => {
+                args.params.block_reward = value.parse().expect("Invalid block reward")
+            }
+            "--treasury" => args.params.treasury = Some(value.parse().expect("Invalid treasury")),
+            _ => panic!("Unknown argument: {}", arg),
+        }
+    }
+    args
+}
+
 fn main() {
-    let (client_to_peer_tx, peer_rx) = mpsc::channel();
-    let (peer_to_client_tx, client_rx) = mpsc::channel();
+    let args = parse_args();
+    let dir = PathBuf::from(format!("data/peer{}", args.id));
+    let storage = Storage::open(&dir, args.params).unwrap();
+    let identity = Identity::load_or_generate(args.key.unwrap_or(dir.join("identity.key")))
+        .expect("Failed to load peer key");
+    let client_identity =
+        Identity::load_or_generate(dir.join("client.key")).expect("Failed to load client key");
+    println!(
+        "Peer {} public key: {}",
+        args.id,
+        to_hex(&identity.public_key.0)
+    );
+    let validators = if args.validators.is_empty() {
+        ValidatorSet::new(vec![Validator {
+            public_key: identity.public_key.clone(),
+            stake: 50,
+        }])
+    } else {
+        ValidatorSet::new(args.validators)
+    };
+    match args.listen {
+        Some(listen) => {
+            let transport =
+                TcpTransport::start(args.id, listen, args.peers).expect("Failed to start network");
+            let client_to_peer_tx = transport.sender();
+            run(
+                args.id,
+                transport,
+                client_to_peer_tx,
+                storage,
+                identity,
+                client_identity,
+                validators,
+            );
+        }
+        None => {
+            let network = InMemoryNetwork::new();
+            let transport = network.join(args.id);
+            let client_to_peer_tx = network.sender(args.id).unwrap();
+            run(
+                args.id,
+                transport,
+                client_to_peer_tx,
+                storage,
+                identity,
+                client_identity,
+                validators,
+            );
+        }
+    }
+}
 
-    let (public_key, private_key) = Ed25519Sha512::new().keypair(None).unwrap();
+// Запускает пира поверх транспорта и клиента, который отправляет ему транзакции.
+fn run<T>(
+    id: u8,
+    transport: T,
+    client_to_peer_tx: Sender<Message>,
+    storage: Storage,
+    identity: Identity,
+    client_identity: Identity,
+    validators: ValidatorSet,
+) where
+    T: Transport + std::fmt::Debug + Send + 'static,
+{
+    let (peer_to_client_tx, client_rx) = mpsc::channel();
 
-    let client_to_peer_tx_clone = client_to_peer_tx.clone();
-    let client = Arc::new(Mutex::new(Client::new(
-        client_to_peer_tx_clone,
-        public_key.clone(),
-        private_key,
+    let client = Arc::new(Client::new(
+        client_to_peer_tx,
+        client_identity.public_key,
+        client_identity.private_key,
         client_rx,
-    )));
+    ));
 
     let peer = Peer::new(
-        1,
-        peer_rx,
-        vec![client_to_peer_tx],
-        50,
-        vec![public_key],
+        id,
+        transport,
+        identity,
+        validators,
         peer_to_client_tx,
+        storage,
     );
 
-    let peer_handle = thread::spawn(move || {
+    // Пир и поток обновлений клиента работают, пока пользователь не выйдет из CLI.
+    thread::spawn(move || {
         peer.start().unwrap();
     });
 
     let client_clone = Arc::clone(&client);
-    let client_handle = thread::spawn(move || {
-        client_clone.lock().unwrap().receive_updates();
+    thread::spawn(move || {
+        client_clone.receive_updates();
     });
 
     run_cli(client);
-
-    peer_handle.join().unwrap();
-    client_handle.join().unwrap();
 }
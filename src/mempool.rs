@@ -0,0 +1,349 @@
+use std::collections::{BTreeMap, HashMap};
+
+use thiserror::Error;
+
+use crate::comands::{Command, Transaction};
+use crate::crypto::Hash;
+use crate::encoding::encode_versioned;
+use crate::identity::to_hex;
+
+#[derive(Debug, Error)]
+pub enum MempoolError {
+    #[error("transaction is already in the mempool")]
+    AlreadyKnown,
+
+    #[error("invalid transaction signature")]
+    InvalidSignature,
+
+    #[error("transaction of {0} bytes exceeds the mempool size limit")]
+    TooLarge(usize),
+
+    #[error("mempool is full and the transaction fee is too low")]
+    Full,
+}
+
+#[derive(Debug, Clone)]
+struct PendingTransaction {
+    transaction: Transaction,
+    size: usize,
+}
+
+// Очередь подписанных транзакций, ожидающих включения в блок. Транзакции одного
+// подписанта хранятся по возрастанию nonce и выбираются только в этом порядке,
+// а между подписантами предпочтение отдается большей комиссии.
+#[derive(Debug)]
+pub struct Mempool {
+    transactions: HashMap<Hash, PendingTransaction>,
+    // Хэши транзакций каждого подписанта, упорядоченные по nonce.
+    by_signer: HashMap<String, BTreeMap<u64, Hash>>,
+    bytes: usize,
+    max_transactions: usize,
+    max_bytes: usize,
+}
+
+impl Mempool {
+    pub fn new(max_transactions: usize, max_bytes: usize) -> Self {
+        Self {
+            transactions: HashMap::new(),
+            by_signer: HashMap::new(),
+            bytes: 0,
+            max_transactions,
+            max_bytes,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.transactions.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.transactions.is_empty()
+    }
+
+    // Добавляет транзакцию. Если очередь заполнена, вытесняются транзакции с меньшей
+    // комиссией; транзакция с тем же nonce от того же подписанта заменяется, только
+    // если ее комиссия больше.
+    pub fn insert(&mut self, transaction: Transaction) -> Result<Hash, MempoolError> {
+        let hash = transaction.hash();
+        if self.transactions.contains_key(&hash) {
+            return Err(MempoolError::AlreadyKnown);
+        }
+        if !transaction.verify_signature() {
+            return Err(MempoolError::InvalidSignature);
+        }
+        let size = encode_versioned(&transaction).len();
+        if size > self.max_bytes {
+            return Err(MempoolError::TooLarge(size));
+        }
+
+        let signer = queue_key(&transaction, &hash);
+        let replaced = self
+            .by_signer
+            .get(&signer)
+            .and_then(|queue| queue.get(&transaction.nonce))
+            .cloned();
+        if let Some(replaced) = &replaced {
+            if self.transactions[replaced].transaction.fee() >= transaction.fee() {
+                return Err(MempoolError::AlreadyKnown);
+            }
+        }
+
+        // Сначала проверяем, что места хватит, и только потом что-то удаляем.
+        let mut evicted = Vec::new();
+        let mut count = self.len() - usize::from(replaced.is_some());
+        let mut bytes = self.bytes
+            - replaced
+                .as_ref()
+                .map_or(0, |hash| self.transactions[hash].size);
+        let mut queues = self.eviction_queues(&signer);
+        while count + 1 > self.max_transactions || bytes + size > self.max_bytes {
+            let Some(candidate) = self.next_eviction(&mut queues) else {
+                return Err(MempoolError::Full);
+            };
+            let pending = &self.transactions[&candidate];
+            if pending.transaction.fee() >= transaction.fee() {
+                return Err(MempoolError::Full);
+            }
+            count -= 1;
+            bytes -= pending.size;
+            evicted.push(candidate);
+        }
+        for hash in evicted.into_iter().chain(replaced) {
+            self.remove(&hash);
+        }
+
+        self.bytes += size;
+        self.by_signer
+            .entry(signer)
+            .or_default()
+            .insert(transaction.nonce, hash.clone());
+        self.transactions
+            .insert(hash.clone(), PendingTransaction { transaction, size });
+        Ok(hash)
+    }
+
+    // Очереди подписантов по возрастанию nonce; вытеснение снимает транзакции с конца.
+    // Очередь подписанта новой транзакции не трогаем — иначе можно вытеснить ее предшественников.
+    fn eviction_queues(&self, except_signer: &str) -> Vec<Vec<&Hash>> {
+        self.by_signer
+            .iter()
+            .filter(|(signer, _)| signer.as_str() != except_signer)
+            .map(|(_, queue)| queue.values().collect())
+            .collect()
+    }
+
+    // Следующий кандидат на вытеснение. Кандидатами служат только последние по nonce
+    // транзакции подписантов, чтобы не оставлять пропусков; среди них выбирается
+    // наименьшая комиссия, при равной — больший хэш. Выбранная транзакция снимается
+    // с конца очереди, и кандидатом становится ее предшественница.
+    fn next_eviction(&self, queues: &mut [Vec<&Hash>]) -> Option<Hash> {
+        let fee = |hash: &Hash| self.transactions[hash].transaction.fee();
+        let queue = queues
+            .iter_mut()
+            .filter(|queue| !queue.is_empty())
+            .min_by(|a, b| {
+                let (a, b) = (a[a.len() - 1], b[b.len() - 1]);
+                fee(a).cmp(&fee(b)).then_with(|| b.cmp(a))
+            })?;
+        queue.pop().cloned()
+    }
+
+    pub fn remove(&mut self, hash: &Hash) -> Option<Transaction> {
+        let pending = self.transactions.remove(hash)?;
+        self.bytes -= pending.size;
+        let signer = queue_key(&pending.transaction, hash);
+        if let Some(queue) = self.by_signer.get_mut(&signer) {
+            queue.remove(&pending.transaction.nonce);
+            if queue.is_empty() {
+                self.by_signer.remove(&signer);
+            }
+        }
+        Some(pending.transaction)
+    }
+
+    // Удаляет транзакции, попавшие в блок.
+    pub fn remove_included(&mut self, data: &[Transaction]) {
+        for transaction in data {
+            self.remove(&transaction.hash());
+        }
+    }
+
+    // Выбирает до limit транзакций для следующего блока: на каждом шаге берется
+    // транзакция с наибольшей комиссией среди первых в очередях подписантов.
+    // При равной комиссии — меньший хэш, чтобы порядок был детерминированным.
+    pub fn select(&self, limit: usize) -> Vec<Transaction> {
+        let mut queues: Vec<_> = self
+            .by_signer
+            .values()
+            .map(|queue| queue.values().peekable())
+            .collect();
+        let mut selected = Vec::new();
+        while selected.len() < limit {
+            let best = queues
+                .iter_mut()
+                .enumerate()
+                .filter_map(|(index, queue)| queue.peek().map(|hash| (index, *hash)))
+                .max_by(|(_, a), (_, b)| {
+                    let fee_a = self.transactions[*a].transaction.fee();
+                    let fee_b = self.transactions[*b].transaction.fee();
+                    fee_a.cmp(&fee_b).then_with(|| b.cmp(a))
+                })
+                .map(|(index, _)| index);
+            let Some(index) = best else {
+                break;
+            };
+            let hash = queues[index].next().unwrap();
+            selected.push(self.transactions[hash].transaction.clone());
+        }
+        selected
+    }
+}
+
+// Очередь транзакции в by_signer. Доказательства нарушений не привязаны к счету и
+// всегда подписываются с nonce 0, поэтому каждое получает собственную очередь по
+// хэшу — иначе второе доказательство того же пира считалось бы заменой первого.
+fn queue_key(transaction: &Transaction, hash: &Hash) -> String {
+    match transaction.command {
+        Command::ReportEquivocation { .. } => to_hex(hash),
+        _ => transaction.signer_public_key.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ursa::keys::{PrivateKey, PublicKey};
+    use ursa::signatures::{prelude::Ed25519Sha512, SignatureScheme};
+
+    use super::*;
+    use crate::amount::Decimal;
+
+    struct User {
+        public_key: PublicKey,
+        private_key: PrivateKey,
+    }
+
+    impl User {
+        fn new() -> Self {
+            let (public_key, private_key) = Ed25519Sha512::new().keypair(None).unwrap();
+            Self {
+                public_key,
+                private_key,
+            }
+        }
+
+        // Транзакция с комиссией fee и заданным nonce.
+        fn pay(&self, nonce: u64, fee: u128) -> Transaction {
+            let command = Command::TransactionCommission {
+                account_id: 1,
+                value: Decimal::new(fee, 0),
+            };
+            Transaction::new(command, nonce, &self.public_key, &self.private_key).unwrap()
+        }
+    }
+
+    fn fees(transactions: &[Transaction]) -> Vec<Decimal> {
+        transactions.iter().map(Transaction::fee).collect()
+    }
+
+    #[test]
+    // проверяет, что повторная транзакция не добавляется, а удаление включенных очищает очередь.
+    fn test_duplicate_transactions_are_rejected() {
+        let alice = User::new();
+        let mut mempool = Mempool::new(10, 1 << 20);
+        let transaction = alice.pay(1, 5);
+        mempool.insert(transaction.clone()).unwrap();
+        assert!(matches!(
+            mempool.insert(transaction.clone()),
+            Err(MempoolError::AlreadyKnown)
+        ));
+        assert_eq!(mempool.len(), 1);
+
+        mempool.remove_included(&[transaction]);
+        assert!(mempool.is_empty());
+        assert_eq!(mempool.bytes, 0);
+    }
+
+    #[test]
+    // проверяет, что выбор идет по комиссии, но транзакции подписанта остаются в порядке nonce.
+    fn test_selection_orders_by_fee_and_nonce() {
+        let (alice, bob) = (User::new(), User::new());
+        let mut mempool = Mempool::new(10, 1 << 20);
+        mempool.insert(alice.pay(2, 50)).unwrap();
+        mempool.insert(alice.pay(1, 1)).unwrap();
+        mempool.insert(bob.pay(1, 10)).unwrap();
+        mempool.insert(bob.pay(2, 3)).unwrap();
+
+        let selected = mempool.select(10);
+        assert_eq!(
+            fees(&selected),
+            [10, 3, 1, 50].map(|fee| Decimal::new(fee, 0))
+        );
+        assert_eq!(mempool.select(2).len(), 2);
+    }
+
+    #[test]
+    // проверяет, что при переполнении вытесняется транзакция с наименьшей комиссией.
+    fn test_full_mempool_evicts_lowest_fee() {
+        let (alice, bob, carol) = (User::new(), User::new(), User::new());
+        let mut mempool = Mempool::new(2, 1 << 20);
+        mempool.insert(alice.pay(1, 5)).unwrap();
+        mempool.insert(bob.pay(1, 1)).unwrap();
+
+        assert!(matches!(
+            mempool.insert(carol.pay(1, 1)),
+            Err(MempoolError::Full)
+        ));
+        mempool.insert(carol.pay(1, 7)).unwrap();
+        assert_eq!(mempool.len(), 2);
+        assert_eq!(
+            fees(&mempool.select(10)),
+            [7, 5].map(|fee| Decimal::new(fee, 0))
+        );
+    }
+
+    #[test]
+    // проверяет, что вытесняется только последняя транзакция подписанта, даже если
+    // у предыдущей комиссия меньше: иначе оставшиеся nonce никогда не выполнятся.
+    fn test_eviction_does_not_leave_nonce_gaps() {
+        let (alice, bob) = (User::new(), User::new());
+        let mut mempool = Mempool::new(2, 1 << 20);
+        mempool.insert(alice.pay(1, 1)).unwrap();
+        mempool.insert(alice.pay(2, 50)).unwrap();
+
+        assert!(matches!(
+            mempool.insert(bob.pay(1, 10)),
+            Err(MempoolError::Full)
+        ));
+        assert_eq!(
+            fees(&mempool.select(10)),
+            [1, 50].map(|fee| Decimal::new(fee, 0))
+        );
+
+        mempool.insert(bob.pay(1, 60)).unwrap();
+        assert_eq!(
+            fees(&mempool.select(10)),
+            [60, 1].map(|fee| Decimal::new(fee, 0))
+        );
+    }
+
+    #[test]
+    // проверяет ограничение по суммарному размеру транзакций.
+    fn test_size_limit_is_enforced() {
+        let alice = User::new();
+        let size = encode_versioned(&alice.pay(1, 1)).len();
+        let mut mempool = Mempool::new(10, size * 2);
+        mempool.insert(alice.pay(1, 1)).unwrap();
+        mempool.insert(alice.pay(2, 1)).unwrap();
+        // Собственные предшественники не вытесняются.
+        assert!(matches!(
+            mempool.insert(alice.pay(3, 9)),
+            Err(MempoolError::Full)
+        ));
+
+        let mut tiny = Mempool::new(10, size - 1);
+        assert!(matches!(
+            tiny.insert(alice.pay(1, 1)),
+            Err(MempoolError::TooLarge(_))
+        ));
+    }
+}
@@ -0,0 +1,713 @@
+use std::collections::HashMap;
+use std::io::{self, Read, Write};
+use std::net::{Shutdown, SocketAddr, TcpListener, TcpStream};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use thiserror::Error;
+
+use crate::comands::{Block, Transaction};
+use crate::encoding::{decode_versioned, encode_versioned, Decode, DecodeError, Encode, Reader};
+use crate::peer::{CommitCertificate, Proposal, Vote};
+
+// Версия сетевого протокола. Пиры с другой версией отключаются после рукопожатия.
+pub const PROTOCOL_VERSION: u32 = 1;
+// Ограничение размера кадра, чтобы некорректная длина не заставила выделить много памяти.
+const MAX_FRAME_LEN: usize = 16 * 1024 * 1024;
+const RECONNECT_INTERVAL: Duration = Duration::from_secs(1);
+// Добавка к интервалу за каждую единицу идентификатора пира, чтобы два пира, одновременно
+// соединяющихся друг с другом, не повторяли попытки в один и тот же момент.
+const RECONNECT_STAGGER: Duration = Duration::from_millis(100);
+
+pub type PeerId = u8;
+
+#[derive(Debug, Error)]
+pub enum TransportError {
+    #[error("peer {0} is not connected")]
+    UnknownPeer(PeerId),
+
+    #[error("failed to send message: {0}")]
+    Send(String),
+}
+
+#[derive(Debug, Clone)]
+pub enum Message {
+    Handshake {
+        version: u32,
+        peer_id: PeerId,
+    },
+    Proposal(Proposal),
+    Transaction(Transaction),
+    Vote(Vote),
+    // Окончательный блок с сертификатом коммита.
+    Commit {
+        block: Block,
+        certificate: CommitCertificate,
+    },
+}
+
+impl Encode for Message {
+    fn encode(&self, out: &mut Vec<u8>) {
+        match self {
+            Self::Handshake { version, peer_id } => {
+                out.push(0);
+                version.encode(out);
+                peer_id.encode(out);
+            }
+            Self::Proposal(proposal) => {
+                out.push(1);
+                proposal.encode(out);
+            }
+            Self::Transaction(transaction) => {
+                out.push(2);
+                transaction.encode(out);
+            }
+            Self::Vote(vote) => {
+                out.push(3);
+                vote.encode(out);
+            }
+            Self::Commit { block, certificate } => {
+                out.push(4);
+                block.encode(out);
+                certificate.encode(out);
+            }
+        }
+    }
+}
+
+impl Decode for Message {
+    fn decode(reader: &mut Reader) -> Result<Self, DecodeError> {
+        let message = match reader.read_u8()? {
+            0 => Self::Handshake {
+                version: Decode::decode(reader)?,
+                peer_id: Decode::decode(reader)?,
+            },
+            1 => Self::Proposal(Decode::decode(reader)?),
+            2 => Self::Transaction(Decode::decode(reader)?),
+            3 => Self::Vote(Decode::decode(reader)?),
+            4 => Self::Commit {
+                block: Decode::decode(reader)?,
+                certificate: Decode::decode(reader)?,
+            },
+            tag => {
+                return Err(DecodeError::InvalidTag {
+                    type_name: "Message",
+                    tag,
+                })
+            }
+        };
+        Ok(message)
+    }
+}
+
+// Кадр: длина тела (u32 BE) и тело — каноническая кодировка сообщения с версией.
+pub fn write_message(stream: &mut impl Write, message: &Message) -> io::Result<()> {
+    let body = encode_versioned(message);
+    stream.write_all(&(body.len() as u32).to_be_bytes())?;
+    stream.write_all(&body)?;
+    stream.flush()
+}
+
+pub fn read_message(stream: &mut impl Read) -> io::Result<Message> {
+    let mut len = [0u8; 4];
+    stream.read_exact(&mut len)?;
+    let len = u32::from_be_bytes(len) as usize;
+    if len > MAX_FRAME_LEN {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("frame too large: {} bytes", len),
+        ));
+    }
+    let mut body = vec![0u8; len];
+    stream.read_exact(&mut body)?;
+    decode_versioned(&body).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+// Способ обмена сообщениями между пирами. Пир не знает, идут ли сообщения через
+// каналы внутри процесса или по сети.
+pub trait Transport {
+    // Пиры, которым сейчас можно отправить сообщение.
+    fn peers(&self) -> Vec<PeerId>;
+
+    fn send(&self, to: PeerId, message: Message) -> Result<(), TransportError>;
+
+    // Ждет входящее сообщение не дольше timeout. None, если сообщений нет.
+    fn receive(&self, timeout: Duration) -> Option<Message>;
+
+    // Рассылает сообщение всем пирам; недоступные пиры пропускаются.
+    fn broadcast(&self, message: &Message) {
+        for peer in self.peers() {
+            let _ = self.send(peer, message.clone());
+        }
+    }
+}
+
+// Сеть внутри одного процесса: у каждого участника свой входящий канал.
+#[derive(Debug, Clone, Default)]
+pub struct InMemoryNetwork {
+    inboxes: Arc<Mutex<HashMap<PeerId, Sender<Message>>>>,
+}
+
+impl InMemoryNetwork {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn join(&self, id: PeerId) -> InMemoryTransport {
+        let (tx, inbox) = mpsc::channel();
+        self.inboxes.lock().unwrap().insert(id, tx);
+        InMemoryTransport {
+            id,
+            network: self.clone(),
+            inbox,
+        }
+    }
+
+    // Канал во входящие сообщения участника, например для клиента этого пира.
+    pub fn sender(&self, id: PeerId) -> Option<Sender<Message>> {
+        self.inboxes.lock().unwrap().get(&id).cloned()
+    }
+}
+
+#[derive(Debug)]
+pub struct InMemoryTransport {
+    id: PeerId,
+    network: InMemoryNetwork,
+    inbox: Receiver<Message>,
+}
+
+impl Transport for InMemoryTransport {
+    fn peers(&self) -> Vec<PeerId> {
+        let mut peers: Vec<PeerId> = self
+            .network
+            .inboxes
+            .lock()
+            .unwrap()
+            .keys()
+            .copied()
+            .collect();
+        peers.retain(|peer| *peer != self.id);
+        peers.sort();
+        peers
+    }
+
+    fn send(&self, to: PeerId, message: Message) -> Result<(), TransportError> {
+        let tx = self
+            .network
+            .sender(to)
+            .ok_or(TransportError::UnknownPeer(to))?;
+        tx.send(message)
+            .map_err(|err| TransportError::Send(err.to_string()))
+    }
+
+    fn receive(&self, timeout: Duration) -> Option<Message> {
+        self.inbox.recv_timeout(timeout).ok()
+    }
+}
+
+// TCP-транспорт между процессами. Входящие сообщения всех соединений собираются
+// в один канал, исходящие пишутся в соединение с нужным пиром.
+#[derive(Debug)]
+pub struct TcpTransport {
+    connections: Arc<TcpConnections>,
+    inbox: Receiver<Message>,
+}
+
+// Каждое соединение пишется под своей блокировкой, чтобы медленный пир не задерживал
+// отправку остальным.
+type SharedStream = Arc<Mutex<TcpStream>>;
+
+#[derive(Debug)]
+struct TcpConnections {
+    peer_id: PeerId,
+    streams: Mutex<HashMap<PeerId, SharedStream>>,
+    inbound: Sender<Message>,
+}
+
+impl TcpTransport {
+    pub fn start(
+        peer_id: PeerId,
+        listen_addr: SocketAddr,
+        peer_addrs: Vec<SocketAddr>,
+    ) -> io::Result<Self> {
+        let (inbound, inbox) = mpsc::channel();
+        let connections = Arc::new(TcpConnections {
+            peer_id,
+            streams: Mutex::new(HashMap::new()),
+            inbound,
+        });
+
+        let listener = TcpListener::bind(listen_addr)?;
+        let acceptor = Arc::clone(&connections);
+        thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                let connections = Arc::clone(&acceptor);
+                thread::spawn(move || connections.handle(stream));
+            }
+        });
+
+        // Исходящие соединения переустанавливаются после разрыва, если пир по этому
+        // адресу не подключился к нам сам.
+        let reconnect_interval = RECONNECT_INTERVAL + RECONNECT_STAGGER * u32::from(peer_id);
+        for addr in peer_addrs {
+            let connector = Arc::clone(&connections);
+            thread::spawn(move || {
+                let mut remote = None;
+                loop {
+                    let connected = remote.is_some_and(|peer| connector.is_connected(peer));
+                    if !connected {
+                        if let Ok(stream) = TcpStream::connect(addr) {
+                            remote = connector.handle(stream).or(remote);
+                        }
+                    }
+                    thread::sleep(reconnect_interval);
+                }
+            });
+        }
+
+        Ok(Self { connections, inbox })
+    }
+
+    // Канал во входящие сообщения пира для клиента в том же процессе.
+    pub fn sender(&self) -> Sender<Message> {
+        self.connections.inbound.clone()
+    }
+}
+
+impl Transport for TcpTransport {
+    fn peers(&self) -> Vec<PeerId> {
+        let mut peers: Vec<PeerId> = self
+            .connections
+            .streams
+            .lock()
+            .unwrap()
+            .keys()
+            .copied()
+            .collect();
+        peers.sort();
+        peers
+    }
+
+    // Соединение с ошибкой записи закрывается и удаляется. Общая таблица соединений
+    // на время записи не блокируется.
+    fn send(&self, to: PeerId, message: Message) -> Result<(), TransportError> {
+        let stream = self
+            .connections
+            .streams
+            .lock()
+            .unwrap()
+            .get(&to)
+            .cloned()
+            .ok_or(TransportError::UnknownPeer(to))?;
+        let mut writer = stream.lock().unwrap();
+        write_message(&mut *writer, &message).map_err(|err| {
+            let _ = writer.shutdown(Shutdown::Both);
+            self.connections.remove(to, &stream);
+            TransportError::Send(err.to_string())
+        })
+    }
+
+    fn receive(&self, timeout: Duration) -> Option<Message> {
+        self.inbox.recv_timeout(timeout).ok()
+    }
+}
+
+impl TcpConnections {
+    // Обслуживает соединение до разрыва и возвращает идентификатор пира из рукопожатия.
+    // Идентификатор ничем не подтвержден, поэтому второе соединение с тем же
+    // идентификатором не вытесняет действующее, а закрывается.
+    fn handle(&self, mut stream: TcpStream) -> Option<PeerId> {
+        let remote = match self.handshake(&mut stream) {
+            Ok(remote) => remote,
+            Err(err) => {
+                println!("Handshake failed: {}", err);
+                return None;
+            }
+        };
+        let writer = Arc::new(Mutex::new(stream.try_clone().ok()?));
+        if !self.register(remote, &writer) {
+            println!(
+                "Peer {} rejected a second connection from peer {}",
+                self.peer_id, remote
+            );
+            return Some(remote);
+        }
+        println!("Peer {} connected to peer {}", self.peer_id, remote);
+        while let Ok(message) = read_message(&mut stream) {
+            if let Message::Handshake { .. } = message {
+                continue;
+            }
+            if self.inbound.send(message).is_err() {
+                break;
+            }
+        }
+        self.remove(remote, &writer);
+        println!("Peer {} disconnected from peer {}", self.peer_id, remote);
+        Some(remote)
+    }
+
+    fn register(&self, remote: PeerId, stream: &SharedStream) -> bool {
+        let mut streams = self.streams.lock().unwrap();
+        if streams.contains_key(&remote) {
+            return false;
+        }
+        streams.insert(remote, Arc::clone(stream));
+        true
+    }
+
+    // Удаляет соединение, только если под этим идентификатором записано именно оно.
+    fn remove(&self, remote: PeerId, stream: &SharedStream) {
+        let mut streams = self.streams.lock().unwrap();
+        if streams
+            .get(&remote)
+            .is_some_and(|current| Arc::ptr_eq(current, stream))
+        {
+            streams.remove(&remote);
+        }
+    }
+
+    fn is_connected(&self, remote: PeerId) -> bool {
+        self.streams.lock().unwrap().contains_key(&remote)
+    }
+
+    fn handshake(&self, stream: &mut TcpStream) -> io::Result<PeerId> {
+        write_message(
+            stream,
+            &Message::Handshake {
+                version: PROTOCOL_VERSION,
+                peer_id: self.peer_id,
+            },
+        )?;
+        match read_message(stream)? {
+            Message::Handshake { peer_id, .. } if peer_id == self.peer_id => Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("peer id {} is our own", peer_id),
+            )),
+            Message::Handshake { version, peer_id } if version == PROTOCOL_VERSION => Ok(peer_id),
+            Message::Handshake { version, .. } => Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("unsupported protocol version {}", version),
+            )),
+            _ => Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "expected handshake",
+            )),
+        }
+    }
+}
+
+// Параметры имитации сети: доля потерянных сообщений в процентах, задержка доставки
+// и ее случайный разброс. Случайность определяется зерном, поэтому тесты повторяемы.
+#[cfg(test)]
+#[derive(Debug, Clone)]
+pub struct SimulationConfig {
+    pub loss_percent: u64,
+    pub latency: Duration,
+    pub jitter: Duration,
+    pub seed: u64,
+}
+
+// Транспорт для тестов сети: поверх другого транспорта теряет и задерживает входящие
+// сообщения. Из-за разброса задержки сообщения могут приходить не по порядку.
+#[cfg(test)]
+#[derive(Debug)]
+pub struct SimulatedTransport<T> {
+    inner: T,
+    config: SimulationConfig,
+    rng: std::cell::Cell<u64>,
+    pending: std::cell::RefCell<Vec<(std::time::Instant, Message)>>,
+}
+
+#[cfg(test)]
+impl<T: Transport> SimulatedTransport<T> {
+    pub fn new(inner: T, config: SimulationConfig) -> Self {
+        Self {
+            inner,
+            rng: std::cell::Cell::new(config.seed),
+            config,
+            pending: std::cell::RefCell::new(Vec::new()),
+        }
+    }
+
+    // splitmix64: простой детерминированный генератор без внешних зависимостей.
+    fn next_random(&self) -> u64 {
+        let state = self.rng.get().wrapping_add(0x9E37_79B9_7F4A_7C15);
+        self.rng.set(state);
+        let mut z = state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+
+    fn delivery_delay(&self) -> Duration {
+        let jitter = self.config.jitter.as_millis() as u64;
+        let extra = if jitter == 0 {
+            0
+        } else {
+            self.next_random() % (jitter + 1)
+        };
+        self.config.latency + Duration::from_millis(extra)
+    }
+}
+
+#[cfg(test)]
+impl<T: Transport> Transport for SimulatedTransport<T> {
+    fn peers(&self) -> Vec<PeerId> {
+        self.inner.peers()
+    }
+
+    fn send(&self, to: PeerId, message: Message) -> Result<(), TransportError> {
+        self.inner.send(to, message)
+    }
+
+    fn receive(&self, timeout: Duration) -> Option<Message> {
+        use std::time::Instant;
+
+        let deadline = Instant::now() + timeout;
+        loop {
+            let now = Instant::now();
+            let mut pending = self.pending.borrow_mut();
+            let earliest = pending
+                .iter()
+                .enumerate()
+                .min_by_key(|(_, (deliver_at, _))| *deliver_at)
+                .map(|(index, (deliver_at, _))| (index, *deliver_at));
+            if let Some((index, deliver_at)) = earliest {
+                if deliver_at <= now {
+                    return Some(pending.remove(index).1);
+                }
+            }
+            if now >= deadline {
+                return None;
+            }
+            let wake_at = earliest.map_or(deadline, |(_, deliver_at)| deliver_at.min(deadline));
+            drop(pending);
+            if let Some(message) = self.inner.receive(wake_at - now) {
+                if self.next_random() % 100 >= self.config.loss_percent {
+                    let deliver_at = Instant::now() + self.delivery_delay();
+                    self.pending.borrow_mut().push((deliver_at, message));
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Instant;
+
+    use super::*;
+    use crate::identity::Identity;
+    use crate::peer::VoteKind;
+
+    fn handshake(version: u32) -> Message {
+        Message::Handshake {
+            version,
+            peer_id: 1,
+        }
+    }
+
+    fn version(message: Option<Message>) -> Option<u32> {
+        match message {
+            Some(Message::Handshake { version, .. }) => Some(version),
+            _ => None,
+        }
+    }
+
+    fn simulated(
+        network: &InMemoryNetwork,
+        id: PeerId,
+        config: SimulationConfig,
+    ) -> SimulatedTransport<InMemoryTransport> {
+        SimulatedTransport::new(network.join(id), config)
+    }
+
+    // Отправляет 20 сообщений через сеть с потерями и возвращает дошедшие.
+    fn delivered_with_loss(seed: u64) -> Vec<u32> {
+        let network = InMemoryNetwork::new();
+        let sender = network.join(1);
+        let receiver = simulated(
+            &network,
+            2,
+            SimulationConfig {
+                loss_percent: 50,
+                latency: Duration::ZERO,
+                jitter: Duration::ZERO,
+                seed,
+            },
+        );
+        for version in 0..20 {
+            sender.send(2, handshake(version)).unwrap();
+        }
+        let mut delivered = Vec::new();
+        while let Some(version) = version(receiver.receive(Duration::from_millis(20))) {
+            delivered.push(version);
+        }
+        delivered
+    }
+
+    // Свободный локальный адрес для слушающего сокета.
+    fn free_addr() -> SocketAddr {
+        TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+    }
+
+    // Ждет выполнения условия не дольше пяти секунд.
+    fn wait_until(condition: impl Fn() -> bool) -> bool {
+        let started = Instant::now();
+        while started.elapsed() < Duration::from_secs(5) {
+            if condition() {
+                return true;
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+        false
+    }
+
+    // Подключается к addr, представляясь пиром peer_id с версией version, и возвращает
+    // соединение после ответного рукопожатия.
+    fn connect_as(addr: SocketAddr, version: u32, peer_id: PeerId) -> TcpStream {
+        let mut stream = TcpStream::connect(addr).unwrap();
+        stream
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        write_message(&mut stream, &Message::Handshake { version, peer_id }).unwrap();
+        assert!(matches!(
+            read_message(&mut stream).unwrap(),
+            Message::Handshake { peer_id: 1, .. }
+        ));
+        stream
+    }
+
+    fn vote_height(message: Option<Message>) -> Option<u64> {
+        match message {
+            Some(Message::Vote(vote)) => Some(vote.height),
+            _ => None,
+        }
+    }
+
+    #[test]
+    // проверяет, что два пира соединяются через loopback, обмениваются сообщениями,
+    // а соединение с уже занятым идентификатором отклоняется и не перехватывает пира.
+    fn test_tcp_loopback_handshake() {
+        let (first_addr, second_addr) = (free_addr(), free_addr());
+        let first = TcpTransport::start(1, first_addr, vec![]).unwrap();
+        let second = TcpTransport::start(2, second_addr, vec![first_addr]).unwrap();
+        assert!(wait_until(
+            || first.peers() == vec![2] && second.peers() == vec![1]
+        ));
+
+        let identity = Identity::generate();
+        let vote = |height| Message::Vote(Vote::new(VoteKind::Prevote, height, 0, None, &identity));
+        second.send(1, vote(7)).unwrap();
+        assert_eq!(vote_height(first.receive(Duration::from_secs(5))), Some(7));
+        first.send(2, vote(8)).unwrap();
+        assert_eq!(vote_height(second.receive(Duration::from_secs(5))), Some(8));
+
+        let mut impostor = connect_as(first_addr, PROTOCOL_VERSION, 2);
+        assert!(read_message(&mut impostor).is_err());
+        first.send(2, vote(9)).unwrap();
+        assert_eq!(vote_height(second.receive(Duration::from_secs(5))), Some(9));
+    }
+
+    #[test]
+    // проверяет, что пир с другой версией протокола отключается и не попадает в список пиров.
+    fn test_tcp_version_mismatch_is_rejected() {
+        let addr = free_addr();
+        let transport = TcpTransport::start(1, addr, vec![]).unwrap();
+
+        let mut stream = connect_as(addr, PROTOCOL_VERSION + 1, 3);
+        assert!(read_message(&mut stream).is_err());
+        assert!(transport.peers().is_empty());
+
+        let _stream = connect_as(addr, PROTOCOL_VERSION, 3);
+        assert!(wait_until(|| transport.peers() == vec![3]));
+    }
+
+    #[test]
+    // проверяет, что сообщение проходит через кадр без изменений, а слишком длинный кадр отклоняется.
+    fn test_frame_roundtrip() {
+        let mut buffer = Vec::new();
+        write_message(&mut buffer, &handshake(PROTOCOL_VERSION)).unwrap();
+        let message = read_message(&mut buffer.as_slice()).ok();
+        assert_eq!(version(message), Some(PROTOCOL_VERSION));
+
+        let oversized = ((MAX_FRAME_LEN + 1) as u32).to_be_bytes();
+        assert!(read_message(&mut oversized.as_slice()).is_err());
+    }
+
+    #[test]
+    // проверяет, что рассылка доходит до всех участников, кроме отправителя.
+    fn test_in_memory_broadcast() {
+        let network = InMemoryNetwork::new();
+        let first = network.join(1);
+        let second = network.join(2);
+        let third = network.join(3);
+
+        assert_eq!(first.peers(), vec![2, 3]);
+        first.broadcast(&handshake(7));
+        assert_eq!(version(second.receive(Duration::ZERO)), Some(7));
+        assert_eq!(version(third.receive(Duration::ZERO)), Some(7));
+        assert!(first.receive(Duration::ZERO).is_none());
+        assert!(matches!(
+            first.send(4, handshake(7)),
+            Err(TransportError::UnknownPeer(4))
+        ));
+    }
+
+    #[test]
+    // проверяет, что сообщение доставляется не раньше заданной задержки.
+    fn test_simulated_latency() {
+        let network = InMemoryNetwork::new();
+        let sender = network.join(1);
+        let receiver = simulated(
+            &network,
+            2,
+            SimulationConfig {
+                loss_percent: 0,
+                latency: Duration::from_millis(100),
+                jitter: Duration::ZERO,
+                seed: 1,
+            },
+        );
+        let started = Instant::now();
+        sender.send(2, handshake(1)).unwrap();
+        assert!(receiver.receive(Duration::from_millis(20)).is_none());
+        assert_eq!(version(receiver.receive(Duration::from_secs(1))), Some(1));
+        assert!(started.elapsed() >= Duration::from_millis(100));
+    }
+
+    #[test]
+    // проверяет, что потери повторяются при одинаковом зерне и теряется только часть сообщений.
+    fn test_simulated_loss_is_deterministic() {
+        let delivered = delivered_with_loss(42);
+        assert!(!delivered.is_empty());
+        assert!(delivered.len() < 20);
+        assert_eq!(delivered, delivered_with_loss(42));
+        // Без разброса задержки порядок сохраняется.
+        assert!(delivered.windows(2).all(|pair| pair[0] < pair[1]));
+    }
+
+    #[test]
+    // проверяет, что сеть, теряющая все сообщения, ничего не доставляет.
+    fn test_simulated_total_loss() {
+        let network = InMemoryNetwork::new();
+        let sender = network.join(1);
+        let receiver = simulated(
+            &network,
+            2,
+            SimulationConfig {
+                loss_percent: 100,
+                latency: Duration::ZERO,
+                jitter: Duration::ZERO,
+                seed: 7,
+            },
+        );
+        sender.broadcast(&handshake(1));
+        assert!(receiver.receive(Duration::from_millis(50)).is_none());
+    }
+}
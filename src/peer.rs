@@ -1,134 +1,888 @@
-use crate::comands::{Block, Command, Transaction};
-use crate::crypto::calculate_random_number;
-use crate::storage::Storage;
-use crate::{crypto, Hash};
-use std::sync::mpsc::{Receiver, Sender};
-
-use std::thread;
-use std::time::Duration;
+use crate::client::Update;
+use crate::comands::{
+    current_timestamp, Block, BlockHeader, BlockchainError, Command, Equivocation, SignedHeader,
+    Transaction,
+};
+use crate::crypto::{self, Hash};
+use crate::encoding::{encode_versioned, Decode, DecodeError, Encode, Reader};
+use crate::identity::{to_hex, Identity};
+use crate::mempool::{Mempool, MempoolError};
+use crate::network::{Message, Transport};
+use crate::storage::{Storage, StorageError};
+use crate::validators::ValidatorSet;
+use std::collections::HashMap;
+use std::sync::mpsc::Sender;
+
+use std::time::{Duration, Instant};
 use thiserror::Error;
-use ursa::signatures::SignatureScheme;
-use ursa::{keys::PublicKey, signatures::prelude::Ed25519Sha512};
+use ursa::keys::PublicKey;
+use ursa::signatures::{prelude::Ed25519Sha512, SignatureScheme};
 #[derive(Debug, Error)]
 pub enum StartError {
     #[error("Failed to start: {0}")]
     ErrStart(String),
 }
 
+// Тайм-ауты шагов раунда. С каждым неудачным раундом они растут на TIMEOUT_DELTA,
+// чтобы при медленной сети пиры успели договориться, но не больше чем до MAX_TIMEOUT_ROUNDS.
+const TIMEOUT_PROPOSE: Duration = Duration::from_millis(1000);
+const TIMEOUT_PREVOTE: Duration = Duration::from_millis(500);
+const TIMEOUT_PRECOMMIT: Duration = Duration::from_millis(500);
+const TIMEOUT_DELTA: Duration = Duration::from_millis(250);
+const MAX_TIMEOUT_ROUNDS: u32 = 8;
+// Допустимое расхождение часов пиров при проверке времени блока.
+const MAX_CLOCK_DRIFT_MS: u64 = 500;
+const MAX_BLOCK_TRANSACTIONS: usize = 100;
+const MEMPOOL_MAX_TRANSACTIONS: usize = 10_000;
+const MEMPOOL_MAX_BYTES: usize = 4 * 1024 * 1024;
+
+fn timeout(base: Duration, round: u32) -> Duration {
+    base + TIMEOUT_DELTA * round.min(MAX_TIMEOUT_ROUNDS)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum VoteKind {
+    Prevote,
+    Precommit,
+}
+
+// Голос валидатора за блок раунда. block_hash = None — голос "ни за один блок" (nil).
+#[derive(Debug, Clone)]
+pub struct Vote {
+    pub kind: VoteKind,
+    pub height: u64,
+    pub round: u32,
+    pub block_hash: Option<Hash>,
+    pub validator: PublicKey,
+    pub signature: Vec<u8>,
+}
+
+impl Vote {
+    pub fn new(
+        kind: VoteKind,
+        height: u64,
+        round: u32,
+        block_hash: Option<Hash>,
+        identity: &Identity,
+    ) -> Self {
+        let signature = Ed25519Sha512::new()
+            .sign(
+                &Self::signing_bytes(kind, height, round, &block_hash),
+                &identity.private_key,
+            )
+            .unwrap();
+        Self {
+            kind,
+            height,
+            round,
+            block_hash,
+            validator: identity.public_key.clone(),
+            signature,
+        }
+    }
+
+    fn signing_bytes(
+        kind: VoteKind,
+        height: u64,
+        round: u32,
+        block_hash: &Option<Hash>,
+    ) -> Vec<u8> {
+        let mut out = Vec::new();
+        kind.encode(&mut out);
+        height.encode(&mut out);
+        round.encode(&mut out);
+        block_hash.encode(&mut out);
+        encode_versioned(&out)
+    }
+
+    pub fn verify_signature(&self) -> bool {
+        Ed25519Sha512::new()
+            .verify(
+                &Self::signing_bytes(self.kind, self.height, self.round, &self.block_hash),
+                &self.signature,
+                &self.validator,
+            )
+            .unwrap_or(false)
+    }
+}
+
+// Предложение блока ведущим раунда. Блок мог быть создан в более раннем раунде:
+// ведущий повторно предлагает блок, за который уже набралось более 2/3 превотов.
+#[derive(Debug, Clone)]
+pub struct Proposal {
+    pub round: u32,
+    pub block: Block,
+    pub proposer: PublicKey,
+    pub signature: Vec<u8>,
+}
+
+impl Proposal {
+    pub fn new(round: u32, block: Block, identity: &Identity) -> Self {
+        let signature = Ed25519Sha512::new()
+            .sign(
+                &Self::signing_bytes(round, &crypto::hash(&block)),
+                &identity.private_key,
+            )
+            .unwrap();
+        Self {
+            round,
+            block,
+            proposer: identity.public_key.clone(),
+            signature,
+        }
+    }
+
+    fn signing_bytes(round: u32, block_hash: &Hash) -> Vec<u8> {
+        let mut out = Vec::new();
+        round.encode(&mut out);
+        block_hash.encode(&mut out);
+        encode_versioned(&out)
+    }
+
+    pub fn verify_signature(&self) -> bool {
+        Ed25519Sha512::new()
+            .verify(
+                &Self::signing_bytes(self.round, &crypto::hash(&self.block)),
+                &self.signature,
+                &self.proposer,
+            )
+            .unwrap_or(false)
+    }
+}
+
+// Сертификат коммита: прекоммиты за блок от валидаторов, у которых в сумме больше 2/3
+// стейка. Блок с таким сертификатом окончателен и не может быть отменен.
+#[derive(Debug, Clone)]
+pub struct CommitCertificate {
+    pub height: u64,
+    pub round: u32,
+    pub block_hash: Hash,
+    pub precommits: Vec<Vote>,
+}
+
+impl CommitCertificate {
+    pub fn verify(&self, validators: &ValidatorSet) -> bool {
+        let mut signers: Vec<&PublicKey> = Vec::new();
+        let mut stake = 0;
+        for vote in &self.precommits {
+            let matches = vote.kind == VoteKind::Precommit
+                && vote.height == self.height
+                && vote.round == self.round
+                && vote.block_hash.as_ref() == Some(&self.block_hash);
+            if !matches || signers.contains(&&vote.validator) || !vote.verify_signature() {
+                return false;
+            }
+            signers.push(&vote.validator);
+            stake += u128::from(validators.stake_of(&vote.validator));
+        }
+        validators.is_quorum(stake)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Step {
+    Propose,
+    Prevote,
+    Precommit,
+}
+
+// Состояние консенсуса на текущей высоте.
 #[derive(Debug)]
-pub struct Peer {
+struct RoundState {
+    height: u64,
+    round: u32,
+    step: Step,
+    // Момент, когда истекает тайм-аут текущего шага.
+    deadline: Instant,
+    // Блок, за который пир отправил прекоммит, и раунд этого решения. Пока блокировка
+    // не снята, пир голосует только за этот блок.
+    locked: Option<(u32, Block)>,
+    // Последний блок, за который набралось более 2/3 превотов; его предлагают повторно.
+    valid: Option<(u32, Block)>,
+    proposals: HashMap<u32, Proposal>,
+    // Голоса по раунду и типу; от каждого валидатора учитывается первый голос.
+    votes: HashMap<(u32, VoteKind), HashMap<Vec<u8>, Vote>>,
+    // Первый увиденный заголовок каждого автора по раунду блока — для поиска двойной подписи.
+    headers: HashMap<(u32, Vec<u8>), SignedHeader>,
+}
+
+impl RoundState {
+    fn new(height: u64) -> Self {
+        Self {
+            height,
+            round: 0,
+            step: Step::Propose,
+            deadline: Instant::now(),
+            locked: None,
+            valid: None,
+            proposals: HashMap::new(),
+            votes: HashMap::new(),
+            headers: HashMap::new(),
+        }
+    }
+
+    fn block(&self, hash: &Hash) -> Option<&Block> {
+        self.proposals
+            .values()
+            .map(|proposal| &proposal.block)
+            .find(|block| &crypto::hash(block) == hash)
+    }
+}
+
+#[derive(Debug)]
+pub struct Peer<T> {
     pub id: u8,
-    pub rx: Receiver<Block>,
-    pub txs: Vec<Sender<Block>>,
+    pub transport: T,
     pub storage: Storage,
-    pub trusted_public_keys: Vec<PublicKey>,
-    pub stake: u32,
-    pub client_tx: Sender<String>,
+    pub identity: Identity,
+    // Начальный набор валидаторов; действует, пока в состоянии нет застейканных валидаторов.
+    pub genesis_validators: ValidatorSet,
+    pub client_tx: Sender<Update>,
+    pub mempool: Mempool,
+    consensus: RoundState,
 }
 
-impl Peer {
+impl<T: Transport> Peer<T> {
     pub fn new(
         id: u8,
-        rx: Receiver<Block>,
-        txs: Vec<Sender<Block>>,
-        stake: u32,
-        trusted_public_keys: Vec<PublicKey>,
-        client_tx: Sender<String>,
+        transport: T,
+        identity: Identity,
+        validators: ValidatorSet,
+        client_tx: Sender<Update>,
+        storage: Storage,
     ) -> Self {
         Self {
             id,
-            rx,
-            txs,
-            trusted_public_keys,
-            stake,
-            storage: Storage::new(),
+            transport,
+            identity,
+            genesis_validators: validators,
+            consensus: RoundState::new(storage.blockchain.len() as u64),
+            storage,
             client_tx,
+            mempool: Mempool::new(MEMPOOL_MAX_TRANSACTIONS, MEMPOOL_MAX_BYTES),
         }
     }
     // start метод запускает работу пира, в ходе которой он участвует в алгоритме консенсуса и обрабатывает входящие и исходящие блоки
     pub fn start(mut self) -> Result<(), StartError> {
-        const TOTAL_STAKE: u32 = 100;
-        for i in 0..3 {
-            println!("ROUND: {} ____________", i);
-            println!("{:?}", &self);
-            thread::sleep(Duration::from_millis(1000));
-            let prev_block_hash = match self.storage.blockchain.last() {
-                Some(block) => crypto::hash(block),
-                None => vec![],
+        self.storage
+            .verify_chain()
+            .map_err(|err| StartError::ErrStart(err.to_string()))?;
+        self.start_round(0)?;
+        loop {
+            self.poll()?;
+        }
+    }
+
+    // Обрабатывает одно входящее сообщение или истекший тайм-аут шага.
+    pub fn poll(&mut self) -> Result<(), StartError> {
+        let deadline = self.consensus.deadline;
+        match self
+            .transport
+            .receive(deadline.saturating_duration_since(Instant::now()))
+        {
+            Some(message) => self.handle_message(message)?,
+            None if Instant::now() >= deadline => self.on_timeout()?,
+            None => {}
+        }
+        self.advance()
+    }
+
+    fn handle_message(&mut self, message: Message) -> Result<(), StartError> {
+        match message {
+            Message::Transaction(transaction) => self.accept_transaction(transaction),
+            Message::Proposal(proposal) => self.on_proposal(proposal),
+            Message::Vote(vote) => self.on_vote(vote),
+            Message::Commit { block, certificate } => self.on_commit(block, certificate)?,
+            Message::Handshake { .. } => {}
+        }
+        Ok(())
+    }
+
+    // Начинает раунд на текущей высоте. Ведущий раунда предлагает блок: повторно тот,
+    // за который уже было большинство превотов, иначе новый из мемпула.
+    fn start_round(&mut self, round: u32) -> Result<(), StartError> {
+        self.consensus.round = round;
+        self.consensus.step = Step::Propose;
+        self.consensus.deadline = Instant::now() + timeout(TIMEOUT_PROPOSE, round);
+        println!(
+            "PEER {} HEIGHT {} ROUND {} ____________",
+            self.id, self.consensus.height, round
+        );
+        if !self.should_propose_block(round) {
+            return Ok(());
+        }
+        let block = match self.consensus.valid.clone() {
+            Some((_, block)) => Some(block),
+            None => self
+                .create_block(round)
+                .map_err(|err| StartError::ErrStart(format!("Failed to create block: {}", err)))?,
+        };
+        if let Some(block) = block {
+            let proposal = Proposal::new(round, block, &self.identity);
+            self.transport
+                .broadcast(&Message::Proposal(proposal.clone()));
+            self.on_proposal(proposal);
+        }
+        Ok(())
+    }
+
+    fn on_timeout(&mut self) -> Result<(), StartError> {
+        let round = self.consensus.round;
+        match self.consensus.step {
+            Step::Propose => self.prevote(None),
+            Step::Prevote => self.precommit(None),
+            Step::Precommit => self.start_round(round + 1)?,
+        }
+        Ok(())
+    }
+
+    // Принимает предложение текущей высоты, если его прислал ведущий раунда,
+    // а блок создан ведущим своего раунда и может быть добавлен на вершину цепочки.
+    fn on_proposal(&mut self, proposal: Proposal) {
+        if proposal.block.header.height != self.consensus.height {
+            return;
+        }
+        self.detect_equivocation(&proposal.block);
+        if self.consensus.proposals.contains_key(&proposal.round) {
+            return;
+        }
+        let seed = self.storage.tip_hash().unwrap_or_default();
+        let from_leader = self
+            .validators()
+            .leader(&seed, self.consensus.height, proposal.round)
+            .is_some_and(|leader| leader.public_key == proposal.proposer);
+        if !from_leader
+            || !proposal.verify_signature()
+            || proposal.block.header.round > proposal.round
+            || !self.is_valid_block(&proposal.block)
+        {
+            return;
+        }
+        if let Err(err) = self.storage.validate_block(&proposal.block) {
+            self.client_tx
+                .send(Update::Text(format!("Block rejected: {}", err)))
+                .unwrap();
+            return;
+        }
+        self.consensus.proposals.insert(proposal.round, proposal);
+    }
+
+    // Два разных блока одного автора на текущей высоте в одном раунде — двойная подпись.
+    // Доказательство рассылается транзакцией, подписанной ключом пира; наказать можно
+    // только валидатора, зарегистрированного в состоянии.
+    fn detect_equivocation(&mut self, block: &Block) {
+        if !block.verify_signature() {
+            return;
+        }
+        let key = (block.header.round, block.signer_public_key.0.clone());
+        let Some(first) = self.consensus.headers.get(&key) else {
+            self.consensus.headers.insert(key, block.signed_header());
+            return;
+        };
+        let evidence = Equivocation {
+            first: first.clone(),
+            second: block.signed_header(),
+        };
+        let staking = &self.storage.state.staking;
+        if !evidence.verify()
+            || staking
+                .validator_account(&to_hex(&block.signer_public_key.0))
+                .is_none()
+        {
+            return;
+        }
+        self.client_tx
+            .send(Update::Text(format!(
+                "Equivocation detected at height {} round {}",
+                block.header.height, block.header.round
+            )))
+            .unwrap();
+        match Transaction::new(
+            Command::ReportEquivocation {
+                evidence: Box::new(evidence),
+            },
+            0,
+            &self.identity.public_key,
+            &self.identity.private_key,
+        ) {
+            Ok(transaction) => self.accept_transaction(transaction),
+            Err(err) => self
+                .client_tx
+                .send(Update::Text(format!("Failed to sign evidence: {}", err)))
+                .unwrap(),
+        }
+    }
+
+    fn on_vote(&mut self, vote: Vote) {
+        if vote.height != self.consensus.height
+            || self.validators().stake_of(&vote.validator) == 0
+            || !vote.verify_signature()
+        {
+            return;
+        }
+        self.consensus
+            .votes
+            .entry((vote.round, vote.kind))
+            .or_default()
+            .entry(vote.validator.0.clone())
+            .or_insert(vote);
+    }
+
+    // Блок текущей высоты с сертификатом коммита применяется без голосования:
+    // так отставший пир догоняет остальных.
+    // Блоки с сертификатом окончательны, поэтому дерево конкурирующих веток, выбор
+    // тяжелейшей цепочки и откат состояния при смене вершины больше не нужны: цепочка
+    // только растет. Сертификат за другой блок уже зафиксированной высоты означает, что
+    // более трети стейка подписали оба блока; пир сообщает об этом клиенту, но цепочку
+    // не перестраивает.
+    fn on_commit(
+        &mut self,
+        block: Block,
+        certificate: CommitCertificate,
+    ) -> Result<(), StartError> {
+        if block.header.height < self.consensus.height {
+            self.report_conflicting_commit(&block, &certificate);
+            return Ok(());
+        }
+        if block.header.height != self.consensus.height
+            || certificate.height != block.header.height
+            || certificate.block_hash != crypto::hash(&block)
+            || !certificate.verify(&self.validators())
+        {
+            return Ok(());
+        }
+        self.detect_equivocation(&block);
+        self.commit(block, certificate)
+    }
+
+    fn report_conflicting_commit(&self, block: &Block, certificate: &CommitCertificate) {
+        let block_hash = crypto::hash(block);
+        let Some(committed) = self.storage.blockchain.get(block.header.height as usize) else {
+            return;
+        };
+        if certificate.height != block.header.height
+            || certificate.block_hash != block_hash
+            || crypto::hash(committed) == block_hash
+            || !certificate.verify(&self.validators())
+        {
+            return;
+        }
+        self.client_tx
+            .send(Update::Text(format!(
+                "Conflicting commit at height {}: certified block {:?} differs from committed {:?}; committed blocks are final and are not reorganised",
+                block.header.height,
+                block_hash,
+                crypto::hash(committed)
+            )))
+            .unwrap();
+    }
+
+    // Голосующая сила за каждый вариант голосования в раунде. Возвращает вариант,
+    // набравший больше 2/3 стейка: Some(хэш блока) или None (nil).
+    fn quorum(&self, round: u32, kind: VoteKind) -> Option<Option<Hash>> {
+        let validators = self.validators();
+        let mut stakes: HashMap<&Option<Hash>, u128> = HashMap::new();
+        for vote in self.consensus.votes.get(&(round, kind))?.values() {
+            *stakes.entry(&vote.block_hash).or_default() +=
+                u128::from(validators.stake_of(&vote.validator));
+        }
+        stakes
+            .into_iter()
+            .find(|(_, stake)| validators.is_quorum(*stake))
+            .map(|(block_hash, _)| block_hash.clone())
+    }
+
+    // Переходит по шагам раунда, пока это позволяют собранные предложения и голоса.
+    fn advance(&mut self) -> Result<(), StartError> {
+        // Больше 2/3 прекоммитов за известный блок в любом раунде — блок окончателен.
+        let decided = self
+            .consensus
+            .votes
+            .keys()
+            .filter(|(_, kind)| *kind == VoteKind::Precommit)
+            .filter_map(
+                |(round, _)| match self.quorum(*round, VoteKind::Precommit) {
+                    Some(Some(hash)) => Some((*round, hash)),
+                    _ => None,
+                },
+            )
+            .find_map(|(round, hash)| {
+                self.consensus
+                    .block(&hash)
+                    .map(|block| (round, hash, block.clone()))
+            });
+        if let Some((round, block_hash, block)) = decided {
+            let precommits = self.consensus.votes[&(round, VoteKind::Precommit)]
+                .values()
+                .filter(|vote| vote.block_hash.as_ref() == Some(&block_hash))
+                .cloned()
+                .collect();
+            let certificate = CommitCertificate {
+                height: self.consensus.height,
+                round,
+                block_hash,
+                precommits,
             };
-            if self.should_propose_block(TOTAL_STAKE, prev_block_hash) {
-                let block = self.create_block();
-                match self.storage.add_block(block.clone()) {
-                    Ok(()) => {}
-                    Err(err) => {
-                        return Err(StartError::ErrStart(format!(
-                            "Failed to add block: {}",
-                            err
-                        )))
-                    }
-                }
+            return self.commit(block, certificate);
+        }
 
-                for tx in &self.txs {
-                    tx.send(block.clone()).unwrap();
-                }
+        let round = self.consensus.round;
+        if self.consensus.step == Step::Propose {
+            if let Some(proposal) = self.consensus.proposals.get(&round) {
+                let block_hash = crypto::hash(&proposal.block);
+                // Заблокированный пир голосует только за свой блок.
+                let vote = match &self.consensus.locked {
+                    Some((_, locked)) if crypto::hash(locked) != block_hash => None,
+                    _ => Some(block_hash),
+                };
+                self.prevote(vote);
             }
-            while let Ok(block) = self.rx.try_recv() {
-                if self.is_valid_block(&block) {
-                    match self.storage.add_block(block.clone()) {
-                        Ok(()) => {
-                            self.client_tx
-                                .send(format!("Block added: {:?}", block))
-                                .unwrap();
-                        }
-                        Err(err) => {
-                            return Err(StartError::ErrStart(format!(
-                                "Failed to add block: {}",
-                                err
-                            )))
-                        }
+        }
+        if self.consensus.step == Step::Prevote {
+            match self.quorum(round, VoteKind::Prevote) {
+                Some(Some(block_hash)) => {
+                    if let Some(block) = self.consensus.block(&block_hash).cloned() {
+                        self.consensus.locked = Some((round, block.clone()));
+                        self.consensus.valid = Some((round, block));
+                        self.precommit(Some(block_hash));
                     }
                 }
+                Some(None) => self.precommit(None),
+                None => {}
             }
         }
         Ok(())
     }
 
-    pub fn create_block(&self) -> Block {
-        let data = vec![Transaction {
-            command: Command::CreateAccount {
-                public_key: format!("peer{}_key", self.id),
-            },
-        }];
-        let (public_key, private_key) = Ed25519Sha512::new().keypair(None).unwrap();
-        Block {
-            signature: Ed25519Sha512::new()
-                .sign(format!("{:?}", &data).as_bytes(), &private_key)
-                .unwrap(),
-            data,
-            signer_public_key: public_key,
-            previous_block_hash: self.storage.blockchain.last().map(crypto::hash),
+    fn prevote(&mut self, block_hash: Option<Hash>) {
+        self.cast_vote(VoteKind::Prevote, block_hash);
+        self.consensus.step = Step::Prevote;
+        self.consensus.deadline = Instant::now() + timeout(TIMEOUT_PREVOTE, self.consensus.round);
+    }
+
+    fn precommit(&mut self, block_hash: Option<Hash>) {
+        self.cast_vote(VoteKind::Precommit, block_hash);
+        self.consensus.step = Step::Precommit;
+        self.consensus.deadline = Instant::now() + timeout(TIMEOUT_PRECOMMIT, self.consensus.round);
+    }
+
+    // Голосуют только валидаторы; собственный голос учитывается сразу.
+    fn cast_vote(&mut self, kind: VoteKind, block_hash: Option<Hash>) {
+        if self.validators().stake_of(&self.identity.public_key) == 0 {
+            return;
+        }
+        let vote = Vote::new(
+            kind,
+            self.consensus.height,
+            self.consensus.round,
+            block_hash,
+            &self.identity,
+        );
+        self.transport.broadcast(&Message::Vote(vote.clone()));
+        self.on_vote(vote);
+    }
+
+    // Добавляет окончательный блок в цепочку, рассылает его с сертификатом
+    // и переходит к следующей высоте.
+    fn commit(&mut self, block: Block, certificate: CommitCertificate) -> Result<(), StartError> {
+        let known_mints = self.storage.state.mints.len();
+        self.storage
+            .add_block(block.clone())
+            .map_err(|err| StartError::ErrStart(format!("Failed to add block: {}", err)))?;
+        self.mempool.remove_included(&block.data);
+        self.client_tx
+            .send(Update::Text(format!(
+                "Block committed at height {} with {} precommits: {:?}",
+                block.header.height,
+                certificate.precommits.len(),
+                block
+            )))
+            .unwrap();
+        for mint in &self.storage.state.mints[known_mints..] {
+            self.client_tx
+                .send(Update::Text(format!(
+                    "Minted {} of {} to account {} at height {}",
+                    self.storage.state.decimal(&mint.asset_id, mint.value),
+                    mint.asset_id,
+                    mint.account_id,
+                    block.header.height
+                )))
+                .unwrap();
+        }
+        let state = &self.storage.state;
+        for (asset_id, supply) in &state.supply {
+            self.client_tx
+                .send(Update::Text(format!(
+                    "Supply of {} at height {}: {} ({} issued, {} burned)",
+                    asset_id,
+                    block.header.height,
+                    state.decimal(asset_id, state.total_supply(asset_id)),
+                    state.decimal(asset_id, supply.issued),
+                    state.decimal(asset_id, supply.burned)
+                )))
+                .unwrap();
+        }
+        self.send_inclusion_proofs(&block);
+        self.transport
+            .broadcast(&Message::Commit { block, certificate });
+        self.consensus = RoundState::new(self.storage.blockchain.len() as u64);
+        self.start_round(0)
+    }
+
+    // Добавляет подписанную транзакцию в мемпул и пересылает ее другим пирам.
+    // Уже известные транзакции не пересылаются повторно, чтобы рассылка не зацикливалась.
+    fn accept_transaction(&mut self, transaction: Transaction) {
+        match self.mempool.insert(transaction.clone()) {
+            Ok(hash) => {
+                self.transport.broadcast(&Message::Transaction(transaction));
+                self.client_tx
+                    .send(Update::Text(format!("Transaction accepted: {:?}", hash)))
+                    .unwrap();
+            }
+            Err(MempoolError::AlreadyKnown) => {}
+            Err(err) => self.report_rejected(&transaction, transaction.nonce, err.to_string()),
+        }
+    }
+
+    // Сообщает клиенту об отклоненной транзакции и nonce, с которого подписанту продолжать:
+    // nonce отклоненной транзакции не израсходован и может быть подписан заново.
+    fn report_rejected(&self, transaction: &Transaction, next_nonce: u64, reason: String) {
+        self.client_tx
+            .send(Update::Rejected {
+                signer: transaction.signer_public_key.clone(),
+                next_nonce,
+                reason,
+            })
+            .unwrap();
+    }
+
+    // Следующий nonce подписанта по состоянию цепочки: после последней примененной
+    // транзакции его счета или 0, если счета еще нет.
+    fn chain_nonce(&self, transaction: &Transaction) -> u64 {
+        let public_key = transaction.signer_public_key.to_string();
+        self.storage
+            .state
+            .accounts
+            .values()
+            .find(|account| account.public_key == public_key)
+            .map_or(0, |account| account.nonce + 1)
+    }
+
+    // Сообщает клиенту доказательства включения транзакций блока и остатков
+    // затронутых ими активов, которые он может проверить по заголовку блока.
+    fn send_inclusion_proofs(&self, block: &Block) {
+        let height = block.header.height as usize;
+        for (index, transaction) in block.data.iter().enumerate() {
+            if let Ok(proof) = self.storage.transaction_proof(height, index) {
+                self.client_tx
+                    .send(Update::Text(format!(
+                        "Transaction {} included at height {}: {:?}",
+                        index, height, proof
+                    )))
+                    .unwrap();
+            }
+            if let Some((account_id, asset_id)) = transaction.command.holding() {
+                if let Ok(proof) = self.storage.prove_balance(account_id, &asset_id) {
+                    self.client_tx
+                        .send(Update::Text(format!(
+                            "Balance of {} on account {} at height {}: {:?}",
+                            asset_id, account_id, height, proof
+                        )))
+                        .unwrap();
+                }
+            }
         }
     }
 
+    // Собирает блок из транзакций мемпула, которые выполняются поверх текущего состояния.
+    // Транзакции с nonce из будущего остаются ждать предшественников, остальные
+    // невыполнимые удаляются. None, если включать нечего.
+    pub fn create_block(&mut self, round: u32) -> Result<Option<Block>, StorageError> {
+        if self.mempool.is_empty() {
+            return Ok(None);
+        }
+        let candidates = self.mempool.select(MAX_BLOCK_TRANSACTIONS);
+        let (data, rejected) = self.storage.select_executable(candidates);
+        for (transaction, err) in rejected {
+            let next_nonce = match err {
+                BlockchainError::InvalidNonce { expected, got } if got > expected => continue,
+                // Устаревший nonce или повторное создание счета: продолжать нужно с nonce из цепочки.
+                BlockchainError::InvalidNonce { .. } | BlockchainError::AccountExists => {
+                    self.chain_nonce(&transaction)
+                }
+                _ => transaction.nonce,
+            };
+            self.mempool.remove(&transaction.hash());
+            self.report_rejected(&transaction, next_nonce, err.to_string());
+        }
+        if data.is_empty() {
+            return Ok(None);
+        }
+        self.seal_block(data, round).map(Some)
+    }
+
+    // Собирает заголовок для блока поверх текущей вершины и подписывает его ключом пира.
+    // Корень состояния вычисляется пробным выполнением транзакций.
+    pub fn seal_block(&self, data: Vec<Transaction>, round: u32) -> Result<Block, StorageError> {
+        let header = BlockHeader {
+            height: self.storage.blockchain.len() as u64,
+            timestamp: current_timestamp(),
+            proposer_id: self.id,
+            round,
+            previous_block_hash: self.storage.tip_hash(),
+            transactions_root: Block::transactions_root(&data),
+            state_root: self
+                .storage
+                .preview_state_root(&data, &self.identity.public_key)?,
+        };
+        Block::new(
+            header,
+            data,
+            &self.identity.public_key,
+            &self.identity.private_key,
+        )
+        .map_err(|err| StorageError::InvalidHeader(err.to_string()))
+    }
+
     pub fn is_valid_block(&self, block: &Block) -> bool {
-        let verified = Ed25519Sha512::new()
-            .verify(
-                format!("{:?}", &block.data).as_bytes(),
-                &block.signature,
-                &block.signer_public_key,
-            )
-            .is_ok();
-        let is_trusted = self
-            .trusted_public_keys
+        block.verify_signature() && self.is_elected_proposer(block)
+    }
+
+    // Проверяет, что автор блока — ведущий раунда из заголовка, а время блока не раньше
+    // родительского и не в будущем.
+    fn is_elected_proposer(&self, block: &Block) -> bool {
+        let header = &block.header;
+        let seed = header.previous_block_hash.clone().unwrap_or_default();
+        let elected = self
+            .validators()
+            .leader(&seed, header.height, header.round)
+            .is_some_and(|leader| leader.public_key == block.signer_public_key);
+        let parent_timestamp = self
+            .storage
+            .blockchain
+            .last()
+            .map_or(0, |parent| parent.header.timestamp);
+        elected
+            && header.timestamp >= parent_timestamp
+            && header.timestamp <= current_timestamp() + MAX_CLOCK_DRIFT_MS
+    }
+
+    // Набор валидаторов текущей эпохи берется из состояния: его меняют команды стейкинга
+    // на границах эпох. Пока никто не застейкан, действует начальный набор.
+    // Набор из генезиса действует, пока в стейкинг не перешли его валидаторы с кворумом
+    // стейка генезиса. Иначе первый счет, застейкавший одну единицу, стал бы единственным
+    // валидатором сети.
+    pub fn validators(&self) -> ValidatorSet {
+        let validators = self.storage.state.staking.validator_set();
+        if self.genesis_validators.total_stake() == 0 {
+            return validators;
+        }
+        let bonded = self
+            .genesis_validators
             .iter()
-            .any(|key| key == &block.signer_public_key);
+            .filter(|genesis| validators.stake_of(&genesis.public_key) > 0)
+            .map(|genesis| u128::from(genesis.stake))
+            .sum();
+        if self.genesis_validators.is_quorum(bonded) {
+            validators
+        } else {
+            self.genesis_validators.clone()
+        }
+    }
+
+    // Пир предлагает блок, только если он ведущий текущего раунда на следующей высоте.
+    pub fn should_propose_block(&self, round: u32) -> bool {
+        let seed = self.storage.tip_hash().unwrap_or_default();
+        let height = self.storage.blockchain.len() as u64;
+        self.validators()
+            .leader(&seed, height, round)
+            .is_some_and(|leader| leader.public_key == self.identity.public_key)
+    }
+}
 
-        verified && is_trusted
+impl Encode for VoteKind {
+    fn encode(&self, out: &mut Vec<u8>) {
+        out.push(match self {
+            Self::Prevote => 0,
+            Self::Precommit => 1,
+        });
     }
+}
 
-    pub fn should_propose_block(&self, total_stake: u32, prev_block_hash: Hash) -> bool {
-        let random_number = calculate_random_number(prev_block_hash);
-        self.stake as f64 / total_stake as f64 > random_number
+impl Decode for VoteKind {
+    fn decode(reader: &mut Reader) -> Result<Self, DecodeError> {
+        match reader.read_u8()? {
+            0 => Ok(Self::Prevote),
+            1 => Ok(Self::Precommit),
+            tag => Err(DecodeError::InvalidTag {
+                type_name: "VoteKind",
+                tag,
+            }),
+        }
+    }
+}
+
+impl Encode for Vote {
+    fn encode(&self, out: &mut Vec<u8>) {
+        self.kind.encode(out);
+        self.height.encode(out);
+        self.round.encode(out);
+        self.block_hash.encode(out);
+        self.validator.encode(out);
+        self.signature.encode(out);
+    }
+}
+
+impl Decode for Vote {
+    fn decode(reader: &mut Reader) -> Result<Self, DecodeError> {
+        Ok(Self {
+            kind: Decode::decode(reader)?,
+            height: Decode::decode(reader)?,
+            round: Decode::decode(reader)?,
+            block_hash: Decode::decode(reader)?,
+            validator: Decode::decode(reader)?,
+            signature: Decode::decode(reader)?,
+        })
+    }
+}
+
+impl Encode for Proposal {
+    fn encode(&self, out: &mut Vec<u8>) {
+        self.round.encode(out);
+        self.block.encode(out);
+        self.proposer.encode(out);
+        self.signature.encode(out);
+    }
+}
+
+impl Decode for Proposal {
+    fn decode(reader: &mut Reader) -> Result<Self, DecodeError> {
+        Ok(Self {
+            round: Decode::decode(reader)?,
+            block: Decode::decode(reader)?,
+            proposer: Decode::decode(reader)?,
+            signature: Decode::decode(reader)?,
+        })
+    }
+}
+
+impl Encode for CommitCertificate {
+    fn encode(&self, out: &mut Vec<u8>) {
+        self.height.encode(out);
+        self.round.encode(out);
+        self.block_hash.encode(out);
+        self.precommits.encode(out);
+    }
+}
+
+impl Decode for CommitCertificate {
+    fn decode(reader: &mut Reader) -> Result<Self, DecodeError> {
+        Ok(Self {
+            height: Decode::decode(reader)?,
+            round: Decode::decode(reader)?,
+            block_hash: Decode::decode(reader)?,
+            precommits: Decode::decode(reader)?,
+        })
     }
 }
 
@@ -207,3 +961,660 @@ impl Peer {
 //         assert!(peer.is_valid_block(&block));
 //     }
 // }
+
+#[cfg(test)]
+mod tests {
+    use std::sync::mpsc::channel;
+    use std::thread::{self, JoinHandle};
+
+    use super::*;
+    use crate::amount::{Amount, Decimal};
+    use crate::comands::AssetDefinition;
+    use crate::network::{InMemoryNetwork, SimulatedTransport, SimulationConfig};
+    use crate::staking::is_epoch_end;
+    use crate::validators::Validator;
+
+    const TEST_TIMEOUT: Duration = Duration::from_secs(30);
+
+    fn validator_set(identities: &[Identity]) -> ValidatorSet {
+        ValidatorSet::new(
+            identities
+                .iter()
+                .map(|identity| Validator {
+                    public_key: identity.public_key.clone(),
+                    stake: 1,
+                })
+                .collect(),
+        )
+    }
+
+    fn create_account() -> Transaction {
+        let user = Identity::generate();
+        Transaction::new(
+            Command::CreateAccount {
+                public_key: user.public_key.to_string(),
+            },
+            0,
+            &user.public_key,
+            &user.private_key,
+        )
+        .unwrap()
+    }
+
+    // Запускает пира в отдельном потоке до первого окончательного блока и возвращает его хранилище.
+    fn run_peer<T: Transport + Send + 'static>(
+        id: u8,
+        transport: T,
+        identity: Identity,
+        validators: ValidatorSet,
+    ) -> JoinHandle<Storage> {
+        thread::spawn(move || {
+            let (client_tx, _client_rx) = channel();
+            let mut peer = Peer::new(
+                id,
+                transport,
+                identity,
+                validators,
+                client_tx,
+                Storage::new(),
+            );
+            let started = Instant::now();
+            peer.start_round(0).unwrap();
+            while peer.storage.blockchain.is_empty() && started.elapsed() < TEST_TIMEOUT {
+                peer.poll().unwrap();
+            }
+            peer.storage
+        })
+    }
+
+    // Все пиры должны получить один и тот же окончательный блок с транзакцией.
+    fn assert_same_commit(storages: &[Storage], transaction: &Transaction) -> Block {
+        let block = storages[0]
+            .blockchain
+            .first()
+            .expect("no block committed")
+            .clone();
+        assert_eq!(block.data.len(), 1);
+        assert_eq!(block.data[0].hash(), transaction.hash());
+        for storage in storages {
+            assert_eq!(storage.blockchain.len(), 1);
+            assert_eq!(crypto::hash(&storage.blockchain[0]), crypto::hash(&block));
+        }
+        block
+    }
+
+    fn signed(identity: &Identity, command: Command, nonce: u64) -> Transaction {
+        Transaction::new(command, nonce, &identity.public_key, &identity.private_key).unwrap()
+    }
+
+    // Запечатывает блок с транзакциями data и добавляет его в хранилище пира.
+    fn commit_block<T: Transport>(peer: &mut Peer<T>, data: Vec<Transaction>) {
+        let block = peer.seal_block(data, 0).unwrap();
+        peer.storage.add_block(block).unwrap();
+    }
+
+    // Добавляет пустые блоки до конца текущей эпохи, чтобы вступили в силу изменения стейка.
+    fn finish_epoch<T: Transport>(peer: &mut Peer<T>) {
+        while !is_epoch_end(peer.storage.blockchain.len() as u64 - 1) {
+            commit_block(peer, vec![]);
+        }
+    }
+
+    #[test]
+    // проверяет, что четыре валидатора фиксируют одинаковый блок.
+    fn test_validators_commit_the_same_block() {
+        let identities: Vec<Identity> = (0..4).map(|_| Identity::generate()).collect();
+        let validators = validator_set(&identities);
+        let network = InMemoryNetwork::new();
+        let transports: Vec<_> = (1..=4).map(|id| network.join(id)).collect();
+        let transaction = create_account();
+        network
+            .sender(1)
+            .unwrap()
+            .send(Message::Transaction(transaction.clone()))
+            .unwrap();
+
+        let handles: Vec<_> = transports
+            .into_iter()
+            .zip(identities)
+            .enumerate()
+            .map(|(index, (transport, identity))| {
+                run_peer(index as u8 + 1, transport, identity, validators.clone())
+            })
+            .collect();
+        let storages: Vec<Storage> = handles.into_iter().map(|h| h.join().unwrap()).collect();
+        assert_same_commit(&storages, &transaction);
+    }
+
+    #[test]
+    // проверяет, что без ведущего первого раунда пиры переходят к следующему раунду
+    // и фиксируют блок тремя валидаторами из четырех.
+    fn test_round_change_when_leader_is_offline() {
+        let identities: Vec<Identity> = (0..4).map(|_| Identity::generate()).collect();
+        let validators = validator_set(&identities);
+        let offline = validators
+            .leader(&Vec::new(), 0, 0)
+            .unwrap()
+            .public_key
+            .clone();
+        let network = InMemoryNetwork::new();
+        let transaction = create_account();
+
+        let online: Vec<(u8, Identity)> = identities
+            .into_iter()
+            .filter(|identity| identity.public_key != offline)
+            .enumerate()
+            .map(|(index, identity)| (index as u8 + 1, identity))
+            .collect();
+        let transports: Vec<_> = online.iter().map(|(id, _)| network.join(*id)).collect();
+        for (id, _) in &online {
+            network
+                .sender(*id)
+                .unwrap()
+                .send(Message::Transaction(transaction.clone()))
+                .unwrap();
+        }
+        let handles: Vec<_> = online
+            .into_iter()
+            .zip(transports)
+            .map(|((id, identity), transport)| {
+                run_peer(id, transport, identity, validators.clone())
+            })
+            .collect();
+        let storages: Vec<Storage> = handles.into_iter().map(|h| h.join().unwrap()).collect();
+        let block = assert_same_commit(&storages, &transaction);
+        assert!(block.header.round > 0);
+        assert_ne!(block.signer_public_key, offline);
+    }
+
+    #[test]
+    // проверяет, что консенсус достигается в сети, теряющей часть сообщений.
+    fn test_commit_over_lossy_network() {
+        let identities: Vec<Identity> = (0..4).map(|_| Identity::generate()).collect();
+        let validators = validator_set(&identities);
+        let network = InMemoryNetwork::new();
+        let transaction = create_account();
+        let transports: Vec<_> = (1..=4)
+            .map(|id| {
+                let config = SimulationConfig {
+                    loss_percent: 10,
+                    latency: Duration::from_millis(5),
+                    jitter: Duration::from_millis(20),
+                    seed: u64::from(id),
+                };
+                SimulatedTransport::new(network.join(id), config)
+            })
+            .collect();
+        for id in 1..=4 {
+            network
+                .sender(id)
+                .unwrap()
+                .send(Message::Transaction(transaction.clone()))
+                .unwrap();
+        }
+        let handles: Vec<_> = transports
+            .into_iter()
+            .zip(identities)
+            .enumerate()
+            .map(|(index, (transport, identity))| {
+                run_peer(index as u8 + 1, transport, identity, validators.clone())
+            })
+            .collect();
+        let storages: Vec<Storage> = handles.into_iter().map(|h| h.join().unwrap()).collect();
+        assert_same_commit(&storages, &transaction);
+    }
+
+    #[test]
+    // проверяет, что сертификат требует больше 2/3 стейка от разных валидаторов за один блок.
+    fn test_commit_certificate_requires_quorum() {
+        let identities: Vec<Identity> = (0..4).map(|_| Identity::generate()).collect();
+        let validators = validator_set(&identities);
+        let block_hash = vec![1u8; 32];
+        let precommit = |identity: &Identity, block_hash: &Hash| {
+            Vote::new(
+                VoteKind::Precommit,
+                5,
+                0,
+                Some(block_hash.clone()),
+                identity,
+            )
+        };
+        let certificate = |precommits: Vec<Vote>| CommitCertificate {
+            height: 5,
+            round: 0,
+            block_hash: block_hash.clone(),
+            precommits,
+        };
+
+        let quorum: Vec<Vote> = identities[..3]
+            .iter()
+            .map(|identity| precommit(identity, &block_hash))
+            .collect();
+        assert!(certificate(quorum.clone()).verify(&validators));
+        assert!(!certificate(quorum[..2].to_vec()).verify(&validators));
+
+        let mut duplicated = quorum[..2].to_vec();
+        duplicated.push(quorum[0].clone());
+        assert!(!certificate(duplicated).verify(&validators));
+
+        let mut other_block = quorum[..2].to_vec();
+        other_block.push(precommit(&identities[3], &vec![2u8; 32]));
+        assert!(!certificate(other_block).verify(&validators));
+
+        let outsider = Identity::generate();
+        let mut foreign = quorum[..2].to_vec();
+        foreign.push(precommit(&outsider, &block_hash));
+        assert!(!certificate(foreign).verify(&validators));
+    }
+
+    #[test]
+    // проверяет, что счет, застейкавший одну единицу, не вытесняет набор генезиса:
+    // стейкинг вступает в силу, только когда в нем кворум стейка генезиса.
+    fn test_genesis_set_is_kept_until_quorum_bonds() {
+        let genesis: Vec<Identity> = (0..3).map(|_| Identity::generate()).collect();
+        let attacker = Identity::generate();
+        let (client_tx, _client_rx) = channel();
+        let mut peer = Peer::new(
+            1,
+            InMemoryNetwork::new().join(1),
+            Identity::generate(),
+            validator_set(&genesis),
+            client_tx,
+            Storage::new(),
+        );
+        let bond = |identity: &Identity, account_id: u32, nonce: u64| {
+            signed(
+                identity,
+                Command::Bond {
+                    account_id,
+                    public_key: to_hex(&identity.public_key.0),
+                    value: Decimal::new(1, 0),
+                    key_signature: identity.sign_bond(account_id),
+                },
+                nonce,
+            )
+        };
+        let mut data = vec![
+            signed(
+                &attacker,
+                Command::CreateAccount {
+                    public_key: attacker.public_key.to_string(),
+                },
+                0,
+            ),
+            signed(
+                &attacker,
+                Command::RegisterAsset {
+                    asset_id: "currency".to_string(),
+                    definition: AssetDefinition {
+                        name: "Currency".to_string(),
+                        decimals: 0,
+                        issuer_id: 1,
+                        max_supply: None,
+                        transferable: true,
+                        redeemable: false,
+                    },
+                },
+                1,
+            ),
+            signed(
+                &attacker,
+                Command::IssueAsset {
+                    account_id: 1,
+                    asset_id: "currency".to_string(),
+                    value: Decimal::new(100, 0),
+                },
+                2,
+            ),
+            bond(&attacker, 1, 3),
+        ];
+        for (index, identity) in genesis.iter().enumerate() {
+            data.push(signed(
+                identity,
+                Command::CreateAccount {
+                    public_key: identity.public_key.to_string(),
+                },
+                0,
+            ));
+            data.push(signed(
+                &attacker,
+                Command::TransferFunds {
+                    from_account_id: 1,
+                    to_account_id: index as u32 + 2,
+                    value: Decimal::new(10, 0),
+                    asset_id: "currency".to_string(),
+                },
+                index as u64 + 4,
+            ));
+        }
+        commit_block(&mut peer, data);
+        finish_epoch(&mut peer);
+        assert_eq!(peer.validators().stake_of(&attacker.public_key), 0);
+        assert_eq!(peer.validators().stake_of(&genesis[0].public_key), 1);
+
+        // Два из трех валидаторов генезиса — ровно 2/3 стейка, это еще не кворум.
+        commit_block(
+            &mut peer,
+            vec![bond(&genesis[0], 2, 1), bond(&genesis[1], 3, 1)],
+        );
+        finish_epoch(&mut peer);
+        assert_eq!(peer.validators().stake_of(&attacker.public_key), 0);
+        assert_eq!(peer.validators().stake_of(&genesis[2].public_key), 1);
+
+        commit_block(&mut peer, vec![bond(&genesis[2], 4, 1)]);
+        finish_epoch(&mut peer);
+        let validators = peer.validators();
+        assert_eq!(validators.stake_of(&attacker.public_key), 1);
+        assert_eq!(validators.total_stake(), 4);
+    }
+
+    #[test]
+    // проверяет, что два блока валидатора в одном раунде дают доказательство, которое
+    // сжигает его стейк и исключает его из набора.
+    fn test_equivocation_is_reported_and_slashed() {
+        let (offender, owner) = (Identity::generate(), Identity::generate());
+        let (client_tx, _client_rx) = channel();
+        let mut peer = Peer::new(
+            1,
+            InMemoryNetwork::new().join(1),
+            Identity::generate(),
+            validator_set(std::slice::from_ref(&offender)),
+            client_tx,
+            Storage::new(),
+        );
+        // Владелец счета регистрирует ключ нарушителя валидатором.
+        let sign = |command: Command, nonce: u64| {
+            Transaction::new(command, nonce, &owner.public_key, &owner.private_key).unwrap()
+        };
+        let genesis = peer
+            .seal_block(
+                vec![
+                    sign(
+                        Command::CreateAccount {
+                            public_key: owner.public_key.to_string(),
+                        },
+                        0,
+                    ),
+                    sign(
+                        Command::RegisterAsset {
+                            asset_id: "currency".to_string(),
+                            definition: AssetDefinition {
+                                name: "Currency".to_string(),
+                                decimals: 0,
+                                issuer_id: 1,
+                                max_supply: None,
+                                transferable: true,
+                                redeemable: false,
+                            },
+                        },
+                        1,
+                    ),
+                    sign(
+                        Command::IssueAsset {
+                            account_id: 1,
+                            asset_id: "currency".to_string(),
+                            value: Decimal::new(100, 0),
+                        },
+                        2,
+                    ),
+                    sign(
+                        Command::Bond {
+                            account_id: 1,
+                            public_key: to_hex(&offender.public_key.0),
+                            value: Decimal::new(40, 0),
+                            key_signature: offender.sign_bond(1),
+                        },
+                        3,
+                    ),
+                ],
+                0,
+            )
+            .unwrap();
+        peer.storage.add_block(genesis).unwrap();
+        peer.consensus = RoundState::new(1);
+
+        for timestamp in [1, 2] {
+            let header = BlockHeader {
+                height: 1,
+                timestamp,
+                proposer_id: 2,
+                round: 0,
+                previous_block_hash: peer.storage.tip_hash(),
+                transactions_root: Block::transactions_root(&[]),
+                state_root: Vec::new(),
+            };
+            let block =
+                Block::new(header, vec![], &offender.public_key, &offender.private_key).unwrap();
+            peer.on_proposal(Proposal::new(0, block, &offender));
+        }
+        assert_eq!(peer.mempool.len(), 1);
+
+        let (data, rejected) = peer.storage.select_executable(peer.mempool.select(10));
+        assert!(rejected.is_empty());
+        let block = peer.seal_block(data, 0).unwrap();
+        peer.storage.add_block(block).unwrap();
+        let staking = &peer.storage.state.staking;
+        assert!(staking.validators.is_empty());
+        assert!(staking.bonds.is_empty());
+        assert_eq!(
+            peer.storage.state.assets[&(1, "currency".to_string())].value,
+            Amount::new(60)
+        );
+    }
+
+    #[test]
+    // проверяет, что два нарушения, замеченные одним пиром, дают два доказательства,
+    // и оба попадают в блок, хотя каждое подписано его ключом с nonce 0.
+    fn test_two_equivocations_are_both_reported() {
+        let offenders = [Identity::generate(), Identity::generate()];
+        let owners = [Identity::generate(), Identity::generate()];
+        let (client_tx, _client_rx) = channel();
+        let mut peer = Peer::new(
+            1,
+            InMemoryNetwork::new().join(1),
+            Identity::generate(),
+            validator_set(&offenders),
+            client_tx,
+            Storage::new(),
+        );
+        let currency = AssetDefinition {
+            name: "Currency".to_string(),
+            decimals: 0,
+            issuer_id: 1,
+            max_supply: None,
+            transferable: true,
+            redeemable: false,
+        };
+        let create = |owner: &Identity| {
+            signed(
+                owner,
+                Command::CreateAccount {
+                    public_key: owner.public_key.to_string(),
+                },
+                0,
+            )
+        };
+        // Каждый владелец регистрирует своего нарушителя валидатором.
+        let bond = |account_id: u32, nonce| {
+            let offender = &offenders[account_id as usize - 1];
+            signed(
+                &owners[account_id as usize - 1],
+                Command::Bond {
+                    account_id,
+                    public_key: to_hex(&offender.public_key.0),
+                    value: Decimal::new(40, 0),
+                    key_signature: offender.sign_bond(account_id),
+                },
+                nonce,
+            )
+        };
+        let data = vec![
+            create(&owners[0]),
+            create(&owners[1]),
+            signed(
+                &owners[0],
+                Command::RegisterAsset {
+                    asset_id: "currency".to_string(),
+                    definition: currency,
+                },
+                1,
+            ),
+            signed(
+                &owners[0],
+                Command::IssueAsset {
+                    account_id: 1,
+                    asset_id: "currency".to_string(),
+                    value: Decimal::new(100, 0),
+                },
+                2,
+            ),
+            signed(
+                &owners[0],
+                Command::TransferFunds {
+                    from_account_id: 1,
+                    to_account_id: 2,
+                    value: Decimal::new(50, 0),
+                    asset_id: "currency".to_string(),
+                },
+                3,
+            ),
+            bond(1, 4),
+            bond(2, 1),
+        ];
+        let genesis = peer.seal_block(data, 0).unwrap();
+        peer.storage.add_block(genesis).unwrap();
+        assert_eq!(peer.storage.state.staking.validators.len(), 2);
+        peer.consensus = RoundState::new(1);
+
+        for offender in &offenders {
+            for timestamp in [1, 2] {
+                let header = BlockHeader {
+                    height: 1,
+                    timestamp,
+                    proposer_id: 2,
+                    round: 0,
+                    previous_block_hash: peer.storage.tip_hash(),
+                    transactions_root: Block::transactions_root(&[]),
+                    state_root: Vec::new(),
+                };
+                let block = Block::new(header, vec![], &offender.public_key, &offender.private_key)
+                    .unwrap();
+                peer.on_proposal(Proposal::new(0, block, offender));
+            }
+        }
+        assert_eq!(peer.mempool.len(), 2);
+
+        let (data, rejected) = peer.storage.select_executable(peer.mempool.select(10));
+        assert!(rejected.is_empty());
+        assert_eq!(data.len(), 2);
+        let block = peer.seal_block(data, 0).unwrap();
+        peer.storage.add_block(block).unwrap();
+        assert!(peer.storage.state.staking.validators.is_empty());
+        assert_eq!(peer.storage.state.total_supply("currency"), Amount::new(20));
+    }
+
+    #[test]
+    // проверяет, что пир сообщает клиенту об отклоненной транзакции и nonce, с которого
+    // подписанту продолжать, а транзакция из будущего остается ждать предшественников.
+    fn test_rejected_transaction_reports_next_nonce() {
+        let alice = Identity::generate();
+        let (client_tx, client_rx) = channel();
+        let mut peer = Peer::new(
+            1,
+            InMemoryNetwork::new().join(1),
+            Identity::generate(),
+            validator_set(&[Identity::generate()]),
+            client_tx,
+            Storage::new(),
+        );
+        let create = Command::CreateAccount {
+            public_key: alice.public_key.to_string(),
+        };
+        commit_block(&mut peer, vec![signed(&alice, create.clone(), 0)]);
+        let unknown = Command::IssueAsset {
+            account_id: 1,
+            asset_id: "gold".to_string(),
+            value: Decimal::new(1, 0),
+        };
+        for transaction in [
+            signed(&alice, create, 0),
+            signed(&alice, unknown.clone(), 1),
+            signed(&alice, unknown, 3),
+        ] {
+            peer.accept_transaction(transaction);
+        }
+        assert!(peer.create_block(0).unwrap().is_none());
+        assert_eq!(peer.mempool.len(), 1);
+
+        let rejected: Vec<u64> = client_rx
+            .try_iter()
+            .filter_map(|update| match update {
+                Update::Rejected {
+                    signer, next_nonce, ..
+                } if signer == alice.public_key => Some(next_nonce),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(rejected.len(), 2);
+        assert!(rejected.iter().all(|nonce| *nonce == 1));
+    }
+
+    #[test]
+    // проверяет, что сертификат за другой блок зафиксированной высоты не перестраивает
+    // цепочку, а только сообщается клиенту.
+    fn test_conflicting_commit_is_reported_without_reorg() {
+        let validator = Identity::generate();
+        let (client_tx, client_rx) = channel();
+        let mut peer = Peer::new(
+            1,
+            InMemoryNetwork::new().join(1),
+            Identity::generate(),
+            validator_set(std::slice::from_ref(&validator)),
+            client_tx,
+            Storage::new(),
+        );
+        commit_block(&mut peer, vec![]);
+        peer.consensus = RoundState::new(1);
+        let committed = peer.storage.blockchain[0].clone();
+        let certify = |block: &Block| CommitCertificate {
+            height: 0,
+            round: 0,
+            block_hash: crypto::hash(block),
+            precommits: vec![Vote::new(
+                VoteKind::Precommit,
+                0,
+                0,
+                Some(crypto::hash(block)),
+                &validator,
+            )],
+        };
+        let conflicting = Block::new(
+            BlockHeader {
+                timestamp: committed.header.timestamp + 1,
+                ..committed.header.clone()
+            },
+            vec![],
+            &validator.public_key,
+            &validator.private_key,
+        )
+        .unwrap();
+
+        peer.on_commit(committed.clone(), certify(&committed))
+            .unwrap();
+        peer.on_commit(conflicting.clone(), certify(&conflicting))
+            .unwrap();
+        let conflicts: Vec<String> = client_rx
+            .try_iter()
+            .filter_map(|update| match update {
+                Update::Text(message) if message.starts_with("Conflicting commit") => Some(message),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(conflicts.len(), 1);
+        assert_eq!(peer.storage.blockchain.len(), 1);
+        assert_eq!(
+            crypto::hash(&peer.storage.blockchain[0]),
+            crypto::hash(&committed)
+        );
+    }
+}
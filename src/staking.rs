@@ -0,0 +1,262 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use crate::amount::Amount;
+use crate::comands::BlockchainError;
+use crate::encoding::{Decode, DecodeError, Encode, Reader};
+use crate::identity::parse_public_key;
+use crate::validators::{Validator, ValidatorSet};
+
+// Длина эпохи в блоках. Изменения стейка вступают в силу только на границе эпохи,
+// поэтому набор валидаторов внутри эпохи не меняется.
+pub const EPOCH_LENGTH: u64 = 10;
+// Наибольший общий стейк. Стейк валидатора в консенсусе — u64, поэтому стейк, который
+// не помещается в u64, не принимается, а не обрезается.
+pub const MAX_TOTAL_STAKE: Amount = Amount::new(u64::MAX as u128);
+
+// Стейкинг в состоянии мира. Стейк списывается с актива "currency" счета и учитывается
+// отдельно до выхода из стейка; выведенные суммы возвращаются на счет в конце следующей
+// эпохи. До возврата они наказываются вместе со стейком, поэтому доказательство нарушения
+// принимается, пока выведенный после нарушения стейк еще не вернулся на счет.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Staking {
+    // Зарегистрированные валидаторы: счет валидатора → открытый ключ пира в hex.
+    pub validators: BTreeMap<u32, String>,
+    // Застейканные суммы: (счет делегатора, счет валидатора) → сумма.
+    pub bonds: BTreeMap<(u32, u32), Amount>,
+    // Суммы, выведенные в текущей эпохе: (счет делегатора, счет валидатора) → сумма.
+    pub unbonding: BTreeMap<(u32, u32), Amount>,
+    // Суммы, выведенные в прошлой эпохе; возвращаются на счет в конце текущей.
+    pub releasing: BTreeMap<(u32, u32), Amount>,
+    // Действующий набор текущей эпохи: счет валидатора → ключ и суммарный стейк.
+    pub active: BTreeMap<u32, ActiveValidator>,
+    // Номер текущей эпохи.
+    pub epoch: u64,
+    // Обработанные доказательства нарушений: (ключ валидатора, высота, раунд). Хранятся,
+    // пока доказательство с такой высотой еще принимается, чтобы его нельзя было повторить.
+    pub evidence: BTreeSet<(String, u64, u32)>,
+}
+
+// Валидатор действующего набора. Ключ запоминается в конце эпохи, поэтому смена ключа
+// при повторной регистрации вступает в силу только со следующей эпохи.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ActiveValidator {
+    pub public_key: String,
+    pub stake: Amount,
+}
+
+// Последний блок эпохи: после него вступают в силу изменения стейка.
+pub fn is_epoch_end(height: u64) -> bool {
+    (height + 1).is_multiple_of(EPOCH_LENGTH)
+}
+
+fn epoch_of(height: u64) -> u64 {
+    height / EPOCH_LENGTH
+}
+
+impl Staking {
+    // Счет валидатора с ключом public_key: действующим в текущей эпохе или
+    // зарегистрированным на следующую.
+    pub fn validator_account(&self, public_key: &str) -> Option<u32> {
+        self.active
+            .iter()
+            .find(|(_, validator)| validator.public_key == public_key)
+            .map(|(account_id, _)| *account_id)
+            .or_else(|| {
+                self.validators
+                    .iter()
+                    .find(|(_, key)| key.as_str() == public_key)
+                    .map(|(account_id, _)| *account_id)
+            })
+    }
+
+    pub fn bonded(&self, delegator_id: u32, validator_id: u32) -> Amount {
+        self.bonds
+            .get(&(delegator_id, validator_id))
+            .copied()
+            .unwrap_or_default()
+    }
+
+    pub fn bond(
+        &mut self,
+        delegator_id: u32,
+        validator_id: u32,
+        value: Amount,
+    ) -> Result<(), BlockchainError> {
+        let total = sum(self.bonds.values().copied())?
+            .checked_add(value)
+            .ok_or(BlockchainError::Overflow)?;
+        if total > MAX_TOTAL_STAKE {
+            return Err(BlockchainError::StakeLimitExceeded);
+        }
+        let bonded = self.bonds.entry((delegator_id, validator_id)).or_default();
+        *bonded = bonded.checked_add(value).ok_or(BlockchainError::Overflow)?;
+        Ok(())
+    }
+
+    // Переводит часть стейка в ожидание возврата; стейка должно хватать.
+    pub fn unbond(
+        &mut self,
+        delegator_id: u32,
+        validator_id: u32,
+        value: Amount,
+    ) -> Result<(), BlockchainError> {
+        let bonded = self
+            .bonded(delegator_id, validator_id)
+            .checked_sub(value)
+            .ok_or(BlockchainError::InsufficientBalance)?;
+        let unbonding = self
+            .unbonding
+            .get(&(delegator_id, validator_id))
+            .copied()
+            .unwrap_or_default()
+            .checked_add(value)
+            .ok_or(BlockchainError::Overflow)?;
+        if bonded.is_zero() {
+            self.bonds.remove(&(delegator_id, validator_id));
+        } else {
+            self.bonds.insert((delegator_id, validator_id), bonded);
+        }
+        self.unbonding
+            .insert((delegator_id, validator_id), unbonding);
+        Ok(())
+    }
+
+    // Запоминает доказательство нарушения валидатора с ключом public_key на высоте height
+    // в раунде round. Повторное и устаревшее доказательства отклоняются.
+    pub fn record_evidence(
+        &mut self,
+        public_key: &str,
+        height: u64,
+        round: u32,
+    ) -> Result<(), BlockchainError> {
+        if epoch_of(height) + 1 < self.epoch {
+            return Err(BlockchainError::EvidenceExpired);
+        }
+        if !self
+            .evidence
+            .insert((public_key.to_string(), height, round))
+        {
+            return Err(BlockchainError::EvidenceAlreadyProcessed);
+        }
+        Ok(())
+    }
+
+    // Сжигает весь стейк, поставленный за валидатора, включая еще не возвращенные
+    // выведенные суммы, и снимает его регистрацию. Валидатор выходит из действующего
+    // набора сразу, не дожидаясь конца эпохи.
+    pub fn slash(&mut self, validator_id: u32) -> Result<Amount, BlockchainError> {
+        let mut slashed = Amount::ZERO;
+        for stakes in [&mut self.bonds, &mut self.unbonding, &mut self.releasing] {
+            let value = sum(stakes
+                .iter()
+                .filter(|((_, bonded_to), _)| *bonded_to == validator_id)
+                .map(|(_, value)| *value))?;
+            slashed = slashed
+                .checked_add(value)
+                .ok_or(BlockchainError::Overflow)?;
+            stakes.retain(|(_, bonded_to), _| *bonded_to != validator_id);
+        }
+        self.validators.remove(&validator_id);
+        self.active.remove(&validator_id);
+        Ok(slashed)
+    }
+
+    // Завершает эпоху: пересчитывает действующий набор по стейкам и возвращает
+    // суммы, выведенные из стейка в прошлой эпохе, — их вызывающий зачисляет на счета.
+    // Выведенные в этой эпохе суммы будут возвращены в конце следующей.
+    pub fn end_epoch(&mut self) -> Result<BTreeMap<u32, Amount>, BlockchainError> {
+        let mut active = BTreeMap::new();
+        for (validator_id, public_key) in &self.validators {
+            let stake = sum(self
+                .bonds
+                .iter()
+                .filter(|((_, bonded_to), _)| bonded_to == validator_id)
+                .map(|(_, value)| *value))?;
+            if !stake.is_zero() {
+                active.insert(
+                    *validator_id,
+                    ActiveValidator {
+                        public_key: public_key.clone(),
+                        stake,
+                    },
+                );
+            }
+        }
+        self.active = active;
+        self.epoch += 1;
+        let epoch = self.epoch;
+        self.evidence
+            .retain(|(_, height, _)| epoch_of(*height) + 1 >= epoch);
+        let mut released = BTreeMap::new();
+        let unbonding = std::mem::take(&mut self.unbonding);
+        for ((delegator_id, _), value) in std::mem::replace(&mut self.releasing, unbonding) {
+            let total: &mut Amount = released.entry(delegator_id).or_default();
+            *total = total.checked_add(value).ok_or(BlockchainError::Overflow)?;
+        }
+        Ok(released)
+    }
+
+    // Набор валидаторов текущей эпохи для консенсуса.
+    pub fn validator_set(&self) -> ValidatorSet {
+        ValidatorSet::new(
+            self.active
+                .values()
+                .filter_map(|validator| {
+                    Some(Validator {
+                        public_key: parse_public_key(&validator.public_key).ok()?,
+                        // Общий стейк не больше MAX_TOTAL_STAKE, поэтому стейк помещается в u64.
+                        stake: u64::try_from(validator.stake.units()).ok()?,
+                    })
+                })
+                .collect(),
+        )
+    }
+}
+
+fn sum(mut values: impl Iterator<Item = Amount>) -> Result<Amount, BlockchainError> {
+    values.try_fold(Amount::ZERO, |total, value| {
+        total.checked_add(value).ok_or(BlockchainError::Overflow)
+    })
+}
+
+impl Encode for ActiveValidator {
+    fn encode(&self, out: &mut Vec<u8>) {
+        self.public_key.encode(out);
+        self.stake.encode(out);
+    }
+}
+
+impl Decode for ActiveValidator {
+    fn decode(reader: &mut Reader) -> Result<Self, DecodeError> {
+        Ok(Self {
+            public_key: Decode::decode(reader)?,
+            stake: Decode::decode(reader)?,
+        })
+    }
+}
+
+impl Encode for Staking {
+    fn encode(&self, out: &mut Vec<u8>) {
+        self.validators.encode(out);
+        self.bonds.encode(out);
+        self.unbonding.encode(out);
+        self.releasing.encode(out);
+        self.active.encode(out);
+        self.epoch.encode(out);
+        self.evidence.encode(out);
+    }
+}
+
+impl Decode for Staking {
+    fn decode(reader: &mut Reader) -> Result<Self, DecodeError> {
+        Ok(Self {
+            validators: Decode::decode(reader)?,
+            bonds: Decode::decode(reader)?,
+            unbonding: Decode::decode(reader)?,
+            releasing: Decode::decode(reader)?,
+            active: Decode::decode(reader)?,
+            epoch: Decode::decode(reader)?,
+            evidence: Decode::decode(reader)?,
+        })
+    }
+}
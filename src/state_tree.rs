@@ -0,0 +1,334 @@
+use std::collections::{BTreeMap, HashMap};
+
+use crate::comands::{Account, Asset, BlockHeader, State};
+use crate::crypto::{hash_bytes, Hash};
+use crate::encoding::Encode;
+
+// Разреженное дерево Меркла глубины 256 над состоянием мира. Ключ записи — хэш
+// идентификатора счета или пары (счет, актив), значение — каноническая кодировка записи.
+// Стейкинг хранится одной записью с фиксированным ключом, описание и масса актива —
+// по идентификатору актива, выпуски — по номеру в журнале.
+const DEPTH: usize = 256;
+const LEAF_PREFIX: u8 = 0;
+const NODE_PREFIX: u8 = 1;
+const ACCOUNT_KEY_PREFIX: u8 = 0;
+const ASSET_KEY_PREFIX: u8 = 1;
+const STAKING_KEY_PREFIX: u8 = 2;
+const SUPPLY_KEY_PREFIX: u8 = 3;
+const DEFINITION_KEY_PREFIX: u8 = 4;
+const MINT_KEY_PREFIX: u8 = 5;
+
+pub type StateKey = [u8; 32];
+
+pub fn account_key(account_id: u32) -> StateKey {
+    let mut bytes = vec![ACCOUNT_KEY_PREFIX];
+    account_id.encode(&mut bytes);
+    to_key(hash_bytes(&bytes))
+}
+
+pub fn asset_key(account_id: u32, asset_id: &str) -> StateKey {
+    let mut bytes = vec![ASSET_KEY_PREFIX];
+    account_id.encode(&mut bytes);
+    asset_id.to_string().encode(&mut bytes);
+    to_key(hash_bytes(&bytes))
+}
+
+pub fn staking_key() -> StateKey {
+    to_key(hash_bytes(&[STAKING_KEY_PREFIX]))
+}
+
+pub fn supply_key(asset_id: &str) -> StateKey {
+    let mut bytes = vec![SUPPLY_KEY_PREFIX];
+    asset_id.to_string().encode(&mut bytes);
+    to_key(hash_bytes(&bytes))
+}
+
+pub fn definition_key(asset_id: &str) -> StateKey {
+    let mut bytes = vec![DEFINITION_KEY_PREFIX];
+    asset_id.to_string().encode(&mut bytes);
+    to_key(hash_bytes(&bytes))
+}
+
+pub fn mint_key(index: u64) -> StateKey {
+    let mut bytes = vec![MINT_KEY_PREFIX];
+    index.encode(&mut bytes);
+    to_key(hash_bytes(&bytes))
+}
+
+fn to_key(hash: Hash) -> StateKey {
+    let mut key = [0u8; 32];
+    key.copy_from_slice(&hash);
+    key
+}
+
+fn bit(key: &StateKey, depth: usize) -> bool {
+    key[depth / 8] & (0x80 >> (depth % 8)) != 0
+}
+
+fn leaf_hash(key: &StateKey, value: &[u8]) -> Hash {
+    hash_bytes(&[&[LEAF_PREFIX], key.as_slice(), value].concat())
+}
+
+fn node_hash(left: &Hash, right: &Hash) -> Hash {
+    hash_bytes(&[&[NODE_PREFIX], left.as_slice(), right.as_slice()].concat())
+}
+
+// Хэши пустых поддеревьев: empty[d] — корень пустого поддерева на глубине d.
+fn empty_hashes() -> Vec<Hash> {
+    let mut empty = vec![vec![0u8; 32]; DEPTH + 1];
+    for depth in (0..DEPTH).rev() {
+        empty[depth] = node_hash(&empty[depth + 1], &empty[depth + 1]);
+    }
+    empty
+}
+
+// Дерево хранится между блоками: листья и хэши поддеревьев, в которых не меньше двух
+// листьев. Хэш поддерева с одним листом вычисляется по листу, пустого — берется из empty.
+// После блока пересчитываются только пути от измененных листьев к корню.
+#[derive(Debug, Clone)]
+pub struct StateTree {
+    leaves: BTreeMap<StateKey, Hash>,
+    nodes: HashMap<(usize, StateKey), Hash>,
+    empty: Vec<Hash>,
+}
+
+impl StateTree {
+    pub fn new(state: &State) -> Self {
+        let initial = State::default();
+        let mut tree = Self {
+            leaves: BTreeMap::new(),
+            nodes: HashMap::new(),
+            empty: empty_hashes(),
+        };
+        // Запись стейкинга есть всегда, даже у пустого состояния.
+        tree.set(staking_key(), Some(&encode(&initial.staking)));
+        tree.update(&initial, state);
+        tree
+    }
+
+    // Переводит дерево состояния old в дерево состояния new, обновляя только
+    // изменившиеся записи. Журнал выпусков только дополняется, поэтому
+    // сравнивается лишь его хвост.
+    pub fn update(&mut self, old: &State, new: &State) {
+        for (account_id, account) in &new.accounts {
+            if old.accounts.get(account_id) != Some(account) {
+                self.set(account_key(*account_id), Some(&account_value(account)));
+            }
+        }
+        for account_id in old.accounts.keys() {
+            if !new.accounts.contains_key(account_id) {
+                self.set(account_key(*account_id), None);
+            }
+        }
+        for ((account_id, asset_id), asset) in &new.assets {
+            if old.assets.get(&(*account_id, asset_id.clone())) != Some(asset) {
+                self.set(asset_key(*account_id, asset_id), Some(&asset_value(asset)));
+            }
+        }
+        for (account_id, asset_id) in old.assets.keys() {
+            if !new.assets.contains_key(&(*account_id, asset_id.clone())) {
+                self.set(asset_key(*account_id, asset_id), None);
+            }
+        }
+        if old.staking != new.staking {
+            self.set(staking_key(), Some(&encode(&new.staking)));
+        }
+        for (asset_id, supply) in &new.supply {
+            if old.supply.get(asset_id) != Some(supply) {
+                self.set(supply_key(asset_id), Some(&encode(supply)));
+            }
+        }
+        for asset_id in old.supply.keys() {
+            if !new.supply.contains_key(asset_id) {
+                self.set(supply_key(asset_id), None);
+            }
+        }
+        for (asset_id, definition) in &new.definitions {
+            if old.definitions.get(asset_id) != Some(definition) {
+                self.set(definition_key(asset_id), Some(&encode(definition)));
+            }
+        }
+        for asset_id in old.definitions.keys() {
+            if !new.definitions.contains_key(asset_id) {
+                self.set(definition_key(asset_id), None);
+            }
+        }
+        for (index, mint) in new.mints.iter().enumerate().skip(old.mints.len()) {
+            self.set(mint_key(index as u64), Some(&encode(mint)));
+        }
+        for index in new.mints.len()..old.mints.len() {
+            self.set(mint_key(index as u64), None);
+        }
+    }
+
+    pub fn root(&self) -> Hash {
+        self.subtree_hash(&[0u8; 32], 0)
+    }
+
+    // Доказательство для ключа: соседние поддеревья на пути от корня к листу.
+    // Подходит и для доказательства отсутствия записи.
+    pub fn prove(&self, key: &StateKey) -> StateProof {
+        let mut siblings = Vec::new();
+        let mut bitmap = vec![0u8; DEPTH / 8];
+        for depth in 0..DEPTH {
+            let sibling = with_bit(key, depth, !bit(key, depth));
+            // Пустые соседние поддеревья не передаются, отмечаются нулевым битом.
+            if self.leaf_count(&sibling, depth + 1) > 0 {
+                bitmap[depth / 8] |= 0x80 >> (depth % 8);
+                siblings.push(self.subtree_hash(&sibling, depth + 1));
+            }
+        }
+        StateProof { bitmap, siblings }
+    }
+
+    // Записывает лист (None — удаляет) и пересчитывает хэши поддеревьев на пути к корню.
+    fn set(&mut self, key: StateKey, value: Option<&[u8]>) {
+        match value {
+            Some(value) => self.leaves.insert(key, leaf_hash(&key, value)),
+            None => self.leaves.remove(&key),
+        };
+        for depth in (0..DEPTH).rev() {
+            let node = (depth, prefix(&key, depth));
+            if self.leaf_count(&key, depth) < 2 {
+                self.nodes.remove(&node);
+                continue;
+            }
+            let left = self.subtree_hash(&with_bit(&key, depth, false), depth + 1);
+            let right = self.subtree_hash(&with_bit(&key, depth, true), depth + 1);
+            self.nodes.insert(node, node_hash(&left, &right));
+        }
+    }
+
+    // Число листьев (0, 1 или 2 — «не меньше двух») в поддереве глубины depth,
+    // содержащем ключ key.
+    fn leaf_count(&self, key: &StateKey, depth: usize) -> usize {
+        self.subtree_leaves(key, depth).take(2).count()
+    }
+
+    fn subtree_leaves(
+        &self,
+        key: &StateKey,
+        depth: usize,
+    ) -> impl Iterator<Item = (&StateKey, &Hash)> {
+        let first = prefix(key, depth);
+        let mut last = first;
+        for position in depth..DEPTH {
+            last[position / 8] |= 0x80 >> (position % 8);
+        }
+        self.leaves.range(first..=last)
+    }
+
+    fn subtree_hash(&self, key: &StateKey, depth: usize) -> Hash {
+        let mut leaves = self.subtree_leaves(key, depth);
+        let Some((leaf_key, leaf)) = leaves.next() else {
+            return self.empty[depth].clone();
+        };
+        if leaves.next().is_some() {
+            return self.nodes[&(depth, prefix(key, depth))].clone();
+        }
+        // Единственный лист поднимается к вершине поддерева через пустых соседей.
+        let mut node = leaf.clone();
+        for level in (depth..DEPTH).rev() {
+            node = if bit(leaf_key, level) {
+                node_hash(&self.empty[level + 1], &node)
+            } else {
+                node_hash(&node, &self.empty[level + 1])
+            };
+        }
+        node
+    }
+}
+
+fn encode<T: Encode>(value: &T) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    value.encode(&mut bytes);
+    bytes
+}
+
+// Ключ с обнуленными битами начиная с depth: вершина поддерева глубины depth.
+fn prefix(key: &StateKey, depth: usize) -> StateKey {
+    let mut prefix = [0u8; 32];
+    for position in 0..depth {
+        if bit(key, position) {
+            prefix[position / 8] |= 0x80 >> (position % 8);
+        }
+    }
+    prefix
+}
+
+// Префикс длины depth + 1: ключ до depth с битом depth, равным value.
+fn with_bit(key: &StateKey, depth: usize, value: bool) -> StateKey {
+    let mut prefix = prefix(key, depth);
+    if value {
+        prefix[depth / 8] |= 0x80 >> (depth % 8);
+    }
+    prefix
+}
+
+pub fn account_value(account: &Account) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    account.encode(&mut bytes);
+    bytes
+}
+
+pub fn asset_value(asset: &Asset) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    asset.encode(&mut bytes);
+    bytes
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StateProof {
+    // Бит d установлен, если соседнее поддерево на глубине d непустое и есть в siblings.
+    bitmap: Vec<u8>,
+    siblings: Vec<Hash>,
+}
+
+impl StateProof {
+    // Проверяет, что по ключу key в дереве с корнем root лежит value (None — записи нет).
+    pub fn verify(&self, root: &Hash, key: &StateKey, value: Option<&[u8]>) -> bool {
+        let empty = empty_hashes();
+        let mut node = match value {
+            Some(value) => leaf_hash(key, value),
+            None => empty[DEPTH].clone(),
+        };
+        let mut siblings = self.siblings.iter().rev();
+        for depth in (0..DEPTH).rev() {
+            let sibling =
+                if self.bitmap.get(depth / 8).copied().unwrap_or(0) & (0x80 >> (depth % 8)) != 0 {
+                    match siblings.next() {
+                        Some(sibling) => sibling.clone(),
+                        None => return false,
+                    }
+                } else {
+                    empty[depth + 1].clone()
+                };
+            node = if bit(key, depth) {
+                node_hash(&sibling, &node)
+            } else {
+                node_hash(&node, &sibling)
+            };
+        }
+        siblings.next().is_none() && &node == root
+    }
+}
+
+// Доказательство остатка актива на счете в состоянии после блока height.
+#[derive(Debug, Clone)]
+pub struct BalanceProof {
+    pub height: u64,
+    pub asset: Option<Asset>,
+    pub proof: StateProof,
+}
+
+impl BalanceProof {
+    pub fn verify(&self, header: &BlockHeader, account_id: u32, asset_id: &str) -> bool {
+        let value = self.asset.as_ref().map(asset_value);
+        header.height == self.height
+            && self.proof.verify(
+                &header.state_root,
+                &asset_key(account_id, asset_id),
+                value.as_deref(),
+            )
+    }
+}
@@ -1,43 +1,1962 @@
 use crate::{
-    comands::{Accounts, Assets, Block},
-    crypto,
+    amount::Amount,
+    comands::{Block, BlockchainError, Params, State, Transaction},
+    crypto::{self, Hash, MerkleProof},
+    encoding::{decode_versioned, encode_versioned, Decode, DecodeError, Encode, Reader},
+    state_tree::{asset_key, BalanceProof, StateTree},
 };
-use std::collections::HashMap;
+use blake2::{Blake2s256, Digest};
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
 use thiserror::Error;
+use ursa::keys::PublicKey;
+
+const BLOCK_LOG_FILE: &str = "blocks.log";
+// Заголовок записи в журнале: длина тела (u32 BE) и контрольная сумма Blake2s тела.
+const RECORD_HEADER_LEN: usize = 4 + 32;
+// Снимок состояния записывается каждые SNAPSHOT_INTERVAL блоков; хранятся последние KEEP_SNAPSHOTS.
+const SNAPSHOT_INTERVAL: usize = 100;
+const KEEP_SNAPSHOTS: usize = 2;
+const SNAPSHOT_PREFIX: &str = "snapshot-";
 
 #[derive(Debug, Error)]
 pub enum StorageError {
     #[error("Failed to execute command: {0}")]
     CommandExecutionError(String),
+
+    #[error("I/O error: {0}")]
+    Io(#[from] io::Error),
+
+    #[error("Corrupted block log at offset {offset}: {reason}")]
+    CorruptedLog { offset: u64, reason: String },
+
+    #[error("Invalid snapshot: {0}")]
+    InvalidSnapshot(String),
+
+    #[error("Block does not extend the chain tip")]
+    InvalidParent,
+
+    #[error("Invalid block header: {0}")]
+    InvalidHeader(String),
+
+    #[error("Block at height {0} not found")]
+    BlockNotFound(usize),
+
+    #[error("Transaction {index} not found in block {block_height}")]
+    TransactionNotFound { block_height: usize, index: usize },
+
+    #[error("Broken chain at height {height}: {reason}")]
+    BrokenChain { height: usize, reason: String },
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug)]
 pub struct Storage {
     pub blockchain: Vec<Block>,
-    pub accounts: Accounts,
-    pub assets: Assets,
+    pub state: State,
+    // Дерево состояния хранится вместе с состоянием и обновляется по изменениям блока.
+    tree: StateTree,
+    params: Params,
+    log: Option<BlockLog>,
+    dir: Option<PathBuf>,
 }
 
 impl Storage {
+    // Хранилище в памяти с параметрами по умолчанию — для тестов.
+    #[cfg(test)]
     pub fn new() -> Self {
+        Self::with_params(Params::default())
+    }
+
+    // Пустая цепочка с начальным состоянием сети для параметров params.
+    pub fn with_params(params: Params) -> Self {
+        let state = State::genesis(&params);
         Self {
             blockchain: Vec::new(),
-            accounts: HashMap::new(),
-            assets: HashMap::new(),
+            tree: StateTree::new(&state),
+            state,
+            params,
+            log: None,
+            dir: None,
+        }
+    }
+
+    // Открывает хранилище в каталоге dir и восстанавливает состояние, переигрывая журнал блоков.
+    // Если есть корректный снимок состояния, переигрываются только блоки после него.
+    pub fn open(dir: impl AsRef<Path>, params: Params) -> Result<Self, StorageError> {
+        let dir = dir.as_ref();
+        fs::create_dir_all(dir)?;
+        let (log, blocks) = BlockLog::open(&dir.join(BLOCK_LOG_FILE))?;
+        let mut storage = Self::with_params(params);
+        if let Some(snapshot) = Snapshot::load_latest(dir, &blocks)? {
+            storage.tree = StateTree::new(&snapshot.state);
+            storage.state = snapshot.state;
+            storage.blockchain = blocks[..snapshot.height as usize].to_vec();
+        }
+        for block in blocks.into_iter().skip(storage.blockchain.len()) {
+            storage.apply_block(&block)?;
+            storage.blockchain.push(block);
+        }
+        storage.log = Some(log);
+        storage.dir = Some(dir.to_path_buf());
+        Ok(storage)
+    }
+
+    // Блок применяется атомарно: команды выполняются на копии состояния,
+    // и только если все прошли успешно, состояние заменяется, а блок добавляется в цепочку.
+    pub fn add_block(&mut self, block: Block) -> Result<(), StorageError> {
+        let (state, tree) = self.check_block(&block)?;
+        if let Some(log) = self.log.as_mut() {
+            log.append(&block)?;
+        }
+        self.state = state;
+        self.tree = tree;
+        self.blockchain.push(block);
+        if self.blockchain.len().is_multiple_of(SNAPSHOT_INTERVAL) {
+            self.write_snapshot()?;
+        }
+        Ok(())
+    }
+
+    // Проверяет, что блок можно добавить на вершину цепочки, не меняя состояние.
+    pub fn validate_block(&self, block: &Block) -> Result<(), StorageError> {
+        self.check_block(block).map(|_| ())
+    }
+
+    // Блок должен ссылаться на текущую вершину цепочки (первый блок — без родителя).
+    // Заголовок проверяется целиком: высота, корень транзакций и корень состояния после выполнения.
+    fn check_block(&self, block: &Block) -> Result<(State, StateTree), StorageError> {
+        if block.header.previous_block_hash != self.tip_hash() {
+            return Err(StorageError::InvalidParent);
+        }
+        if block.header.height != self.blockchain.len() as u64 {
+            return Err(StorageError::InvalidHeader(format!(
+                "expected height {}, got {}",
+                self.blockchain.len(),
+                block.header.height
+            )));
+        }
+        if block.header.transactions_root != Block::transactions_root(&block.data) {
+            return Err(StorageError::InvalidHeader(
+                "transactions root mismatch".to_string(),
+            ));
+        }
+        let state = self.execute_block(&block.data, &block.signer_public_key)?;
+        let tree = self.tree_after(&state);
+        if block.header.state_root != tree.root() {
+            return Err(StorageError::InvalidHeader(
+                "state root mismatch".to_string(),
+            ));
+        }
+        Ok((state, tree))
+    }
+
+    pub fn tip_hash(&self) -> Option<Hash> {
+        self.blockchain.last().map(crypto::hash)
+    }
+
+    // Проходит всю цепочку, пересчитывая хэши родителей и проверяя подписи блоков и транзакций.
+    // Возвращает первое найденное нарушение.
+    pub fn verify_chain(&self) -> Result<(), StorageError> {
+        let mut expected_parent = None;
+        for (height, block) in self.blockchain.iter().enumerate() {
+            let broken = |reason: &str| StorageError::BrokenChain {
+                height,
+                reason: reason.to_string(),
+            };
+            if block.header.previous_block_hash != expected_parent {
+                return Err(broken("previous block hash mismatch"));
+            }
+            if block.header.height != height as u64 {
+                return Err(broken("height mismatch"));
+            }
+            if block.header.transactions_root != Block::transactions_root(&block.data) {
+                return Err(broken("transactions root mismatch"));
+            }
+            if !block.verify_signature() {
+                return Err(broken("invalid block signature"));
+            }
+            if let Some(index) = block.data.iter().position(|tx| !tx.verify_signature()) {
+                return Err(broken(&format!(
+                    "invalid signature of transaction {}",
+                    index
+                )));
+            }
+            expected_parent = Some(crypto::hash(block));
         }
+        Ok(())
+    }
+
+    // Доказательство включения транзакции index в блок block_height относительно
+    // transactions_root его заголовка.
+    pub fn transaction_proof(
+        &self,
+        block_height: usize,
+        index: usize,
+    ) -> Result<MerkleProof, StorageError> {
+        let block = self
+            .blockchain
+            .get(block_height)
+            .ok_or(StorageError::BlockNotFound(block_height))?;
+        let leaves: Vec<Hash> = block.data.iter().map(Transaction::hash).collect();
+        MerkleProof::new(&leaves, index).ok_or(StorageError::TransactionNotFound {
+            block_height,
+            index,
+        })
+    }
+
+    // Доказательство остатка актива asset_id на счете account_id относительно
+    // state_root заголовка последнего блока. Отсутствие записи тоже доказуемо.
+    pub fn prove_balance(
+        &self,
+        account_id: u32,
+        asset_id: &str,
+    ) -> Result<BalanceProof, StorageError> {
+        let last_block = self
+            .blockchain
+            .last()
+            .ok_or(StorageError::BlockNotFound(0))?;
+        Ok(BalanceProof {
+            height: last_block.header.height,
+            asset: self
+                .state
+                .assets
+                .get(&(account_id, asset_id.to_string()))
+                .cloned(),
+            proof: self.tree.prove(&asset_key(account_id, asset_id)),
+        })
+    }
+
+    pub fn state_root(&self) -> Hash {
+        self.tree.root()
+    }
+
+    // Дерево состояния state, полученного из текущего: пересчитываются только изменившиеся записи.
+    fn tree_after(&self, state: &State) -> StateTree {
+        let mut tree = self.tree.clone();
+        tree.update(&self.state, state);
+        tree
+    }
+
+    // Записывает снимок текущего состояния, помеченный высотой и хэшем последнего блока.
+    pub fn write_snapshot(&self) -> Result<(), StorageError> {
+        let (Some(dir), Some(last_block)) = (self.dir.as_ref(), self.blockchain.last()) else {
+            return Ok(());
+        };
+        let snapshot = Snapshot {
+            height: self.blockchain.len() as u64,
+            block_hash: crypto::hash(last_block),
+            state_root: self.state_root(),
+            state: self.state.clone(),
+        };
+        snapshot.write(dir)?;
+        Snapshot::prune(dir)
+    }
+
+    fn apply_block(&mut self, block: &Block) -> Result<(), StorageError> {
+        let state = self.execute_block(&block.data, &block.signer_public_key)?;
+        self.tree.update(&self.state, &state);
+        self.state = state;
+        Ok(())
     }
 
-    pub fn add_block(&mut self, mut block: Block) -> Result<(), StorageError> {
-        for command in block.data.iter().map(|transaction| &transaction.command) {
-            match command.execute(&mut self.accounts, &mut self.assets) {
+    // Корень состояния, которое получится после применения транзакций блока автора proposer
+    // к текущему состоянию.
+    pub fn preview_state_root(
+        &self,
+        data: &[Transaction],
+        proposer: &PublicKey,
+    ) -> Result<Hash, StorageError> {
+        let state = self.execute_block(data, proposer)?;
+        Ok(self.tree_after(&state).root())
+    }
+
+    // Отбирает из кандидатов транзакции, которые последовательно применяются к текущему
+    // состоянию. Остальные возвращаются вместе с ошибкой выполнения.
+    pub fn select_executable(
+        &self,
+        candidates: Vec<Transaction>,
+    ) -> (Vec<Transaction>, Vec<(Transaction, BlockchainError)>) {
+        let mut state = self.state.clone();
+        let mut executable: Vec<Transaction> = Vec::new();
+        let mut rejected = Vec::new();
+        for transaction in candidates {
+            match transaction.execute(&mut state, &self.params) {
+                Ok(()) => executable.push(transaction),
+                Err(err) => {
+                    // Неудачная транзакция могла оставить частичные изменения: состояние
+                    // восстанавливается повторным выполнением уже отобранных транзакций.
+                    state = self.state.clone();
+                    for accepted in &executable {
+                        let replayed = accepted.execute(&mut state, &self.params);
+                        debug_assert!(replayed.is_ok());
+                    }
+                    rejected.push((transaction, err));
+                }
+            }
+        }
+        (executable, rejected)
+    }
+
+    // Выполняет команды блока на копии текущего состояния и возвращает новое состояние.
+    // После транзакций автору начисляются комиссии и награда, а на границе эпохи
+    // обновляется стейкинг.
+    fn execute_block(
+        &self,
+        data: &[Transaction],
+        proposer: &PublicKey,
+    ) -> Result<State, StorageError> {
+        let mut state = self.state.clone();
+        for transaction in data {
+            match transaction.execute(&mut state, &self.params) {
                 Ok(_) => (),
                 Err(e) => return Err(StorageError::CommandExecutionError(e.to_string())),
             }
         }
-        if let Some(last_block) = self.blockchain.last() {
-            block.previous_block_hash = Some(crypto::hash(last_block));
+        let execution_error =
+            |e: BlockchainError| StorageError::CommandExecutionError(e.to_string());
+        let fees = data
+            .iter()
+            .map(Transaction::fee)
+            .filter(|fee| !fee.is_zero())
+            .try_fold(Amount::ZERO, |total, fee| {
+                total
+                    .checked_add(state.units("currency", fee)?)
+                    .ok_or(BlockchainError::Overflow)
+            })
+            .map_err(execution_error)?;
+        state
+            .end_block(
+                self.blockchain.len() as u64,
+                proposer,
+                fees,
+                self.params.block_reward,
+            )
+            .map_err(execution_error)?;
+        Ok(state)
+    }
+}
+
+// Журнал блоков только на дозапись. Каждая запись: [длина][контрольная сумма][блок].
+// Недописанный хвост (обрыв при сбое) обнаруживается при открытии и отрезается.
+#[derive(Debug)]
+struct BlockLog {
+    file: File,
+}
+
+impl BlockLog {
+    fn open(path: &Path) -> Result<(Self, Vec<Block>), StorageError> {
+        let mut file = OpenOptions::new()
+            .read(true)
+            .append(true)
+            .create(true)
+            .open(path)?;
+        let mut contents = Vec::new();
+        file.read_to_end(&mut contents)?;
+
+        let mut blocks = Vec::new();
+        let mut offset = 0usize;
+        while offset < contents.len() {
+            match Self::read_record(&contents[offset..]) {
+                Some(Ok((block, len))) => {
+                    blocks.push(block);
+                    offset += len;
+                }
+                // Запись обрывается на конце файла: сбой посреди записи, отбрасываем хвост.
+                // Если же за ней есть целые записи, испорчено поле длины в середине журнала —
+                // обрезка удалила бы подтвержденные блоки.
+                None if Self::has_record_after(&contents[offset..]) => {
+                    return Err(StorageError::CorruptedLog {
+                        offset: offset as u64,
+                        reason: "invalid record length".to_string(),
+                    })
+                }
+                None => {
+                    file.set_len(offset as u64)?;
+                    file.sync_all()?;
+                    break;
+                }
+                Some(Err(reason)) => {
+                    return Err(StorageError::CorruptedLog {
+                        offset: offset as u64,
+                        reason,
+                    })
+                }
+            }
+        }
+        file.seek(SeekFrom::End(0))?;
+        Ok((Self { file }, blocks))
+    }
+
+    // None — запись неполная (оборванная дозапись), Some(Err) — запись целая, но повреждена.
+    fn read_record(bytes: &[u8]) -> Option<Result<(Block, usize), String>> {
+        if bytes.len() < RECORD_HEADER_LEN {
+            return None;
+        }
+        let len = u32::from_be_bytes(bytes[..4].try_into().unwrap()) as usize;
+        let checksum = &bytes[4..RECORD_HEADER_LEN];
+        let body = bytes.get(RECORD_HEADER_LEN..RECORD_HEADER_LEN + len)?;
+        if Blake2s256::digest(body).as_slice() != checksum {
+            // Повреждённая последняя запись — тоже признак оборванной записи.
+            if bytes.len() == RECORD_HEADER_LEN + len {
+                return None;
+            }
+            return Some(Err("checksum mismatch".to_string()));
+        }
+        Some(
+            Block::deserialize(body)
+                .map(|block| (block, RECORD_HEADER_LEN + len))
+                .map_err(|e| e.to_string()),
+        )
+    }
+
+    // Есть ли в bytes после первого байта целая запись с верной контрольной суммой.
+    fn has_record_after(bytes: &[u8]) -> bool {
+        (1..bytes.len()).any(|start| {
+            let bytes = &bytes[start..];
+            if bytes.len() < RECORD_HEADER_LEN {
+                return false;
+            }
+            let len = u32::from_be_bytes(bytes[..4].try_into().unwrap()) as usize;
+            bytes
+                .get(RECORD_HEADER_LEN..RECORD_HEADER_LEN + len)
+                .is_some_and(|body| {
+                    Blake2s256::digest(body).as_slice() == &bytes[4..RECORD_HEADER_LEN]
+                })
+        })
+    }
+
+    fn append(&mut self, block: &Block) -> Result<(), StorageError> {
+        let body = block.serialize();
+        let mut record = Vec::with_capacity(RECORD_HEADER_LEN + body.len());
+        record.extend_from_slice(&(body.len() as u32).to_be_bytes());
+        record.extend_from_slice(&Blake2s256::digest(&body));
+        record.extend_from_slice(&body);
+        self.file.write_all(&record)?;
+        self.file.sync_data()?;
+        Ok(())
+    }
+}
+
+// Снимок мира после блока с номером height.
+struct Snapshot {
+    height: u64,
+    block_hash: Hash,
+    state_root: Hash,
+    state: State,
+}
+
+impl Snapshot {
+    fn file_name(height: u64) -> String {
+        format!("{}{:020}.bin", SNAPSHOT_PREFIX, height)
+    }
+
+    // Снимки в каталоге, отсортированные от новых к старым.
+    fn list(dir: &Path) -> Result<Vec<PathBuf>, StorageError> {
+        let mut paths = Vec::new();
+        for entry in fs::read_dir(dir)? {
+            let path = entry?.path();
+            let is_snapshot = path
+                .file_name()
+                .and_then(|name| name.to_str())
+                .is_some_and(|name| name.starts_with(SNAPSHOT_PREFIX) && name.ends_with(".bin"));
+            if is_snapshot {
+                paths.push(path);
+            }
+        }
+        paths.sort();
+        paths.reverse();
+        Ok(paths)
+    }
+
+    // Загружает последний снимок, который сходится с журналом блоков и корнем состояния
+    // в заголовке своего блока.
+    // Повреждённые снимки удаляются: состояние тогда восстанавливается по журналу.
+    fn load_latest(dir: &Path, blocks: &[Block]) -> Result<Option<Self>, StorageError> {
+        for path in Self::list(dir)? {
+            match Self::read(&path).and_then(|snapshot| snapshot.verify(blocks).map(|_| snapshot)) {
+                Ok(snapshot) => return Ok(Some(snapshot)),
+                Err(_) => fs::remove_file(&path)?,
+            }
+        }
+        Ok(None)
+    }
+
+    fn read(path: &Path) -> Result<Self, StorageError> {
+        let bytes = fs::read(path)?;
+        decode_versioned(&bytes).map_err(|e| StorageError::InvalidSnapshot(e.to_string()))
+    }
+
+    fn verify(&self, blocks: &[Block]) -> Result<(), StorageError> {
+        let block = match (self.height as usize).checked_sub(1) {
+            Some(index) => blocks.get(index),
+            None => None,
+        }
+        .ok_or_else(|| {
+            StorageError::InvalidSnapshot(format!("no block at height {}", self.height))
+        })?;
+        if crypto::hash(block) != self.block_hash {
+            return Err(StorageError::InvalidSnapshot(
+                "block hash does not match the log".to_string(),
+            ));
+        }
+        // Доверенный корень — из заголовка блока в журнале, а не записанный в самом снимке.
+        let state_root = crypto::state_root(&self.state);
+        if state_root != block.header.state_root || state_root != self.state_root {
+            return Err(StorageError::InvalidSnapshot(
+                "state root mismatch".to_string(),
+            ));
+        }
+        Ok(())
+    }
+
+    // Пишет во временный файл и переименовывает, чтобы не оставить наполовину записанный снимок.
+    fn write(&self, dir: &Path) -> Result<(), StorageError> {
+        let path = dir.join(Self::file_name(self.height));
+        let tmp_path = path.with_extension("tmp");
+        let mut file = File::create(&tmp_path)?;
+        file.write_all(&encode_versioned(self))?;
+        file.sync_all()?;
+        fs::rename(&tmp_path, &path)?;
+        Ok(())
+    }
+
+    fn prune(dir: &Path) -> Result<(), StorageError> {
+        for path in Self::list(dir)?.into_iter().skip(KEEP_SNAPSHOTS) {
+            fs::remove_file(path)?;
         }
-        self.blockchain.push(block);
         Ok(())
     }
 }
+
+impl Encode for Snapshot {
+    fn encode(&self, out: &mut Vec<u8>) {
+        self.height.encode(out);
+        self.block_hash.encode(out);
+        self.state_root.encode(out);
+        self.state.encode(out);
+    }
+}
+
+impl Decode for Snapshot {
+    fn decode(reader: &mut Reader) -> Result<Self, DecodeError> {
+        let height = u64::decode(reader)?;
+        let block_hash = Hash::decode(reader)?;
+        let state_root = Hash::decode(reader)?;
+        Ok(Self {
+            height,
+            block_hash,
+            state_root,
+            state: Decode::decode(reader)?,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ursa::keys::PrivateKey;
+    use ursa::signatures::{prelude::Ed25519Sha512, SignatureScheme};
+
+    use super::*;
+    use crate::amount::Decimal;
+    use crate::client::Client;
+    use crate::comands::{
+        AssetDefinition, BlockHeader, BlockchainError, Command, Equivocation, ExecutionContext,
+        CURRENCY_DECIMALS,
+    };
+    use crate::identity::{to_hex, Identity};
+    use crate::staking::EPOCH_LENGTH;
+
+    struct User {
+        public_key: PublicKey,
+        private_key: PrivateKey,
+        nonce: u64,
+    }
+
+    impl User {
+        fn new() -> Self {
+            let (public_key, private_key) = Ed25519Sha512::new().keypair(None).unwrap();
+            Self {
+                public_key,
+                private_key,
+                nonce: 0,
+            }
+        }
+
+        fn sign(&mut self, command: Command) -> Transaction {
+            let transaction =
+                Transaction::new(command, self.nonce, &self.public_key, &self.private_key).unwrap();
+            self.nonce += 1;
+            transaction
+        }
+
+        fn create_account(&mut self) -> Transaction {
+            let public_key = self.public_key.to_string();
+            self.sign(Command::CreateAccount { public_key })
+        }
+    }
+
+    // Блок, подписанный новым ключом и ссылающийся на вершину цепочки storage.
+    fn block(storage: &Storage, data: Vec<Transaction>) -> Block {
+        block_by(storage, &User::new(), data)
+    }
+
+    // Если транзакции не выполняются, корень состояния остается пустым.
+    fn block_by(storage: &Storage, proposer: &User, data: Vec<Transaction>) -> Block {
+        let header = BlockHeader {
+            height: storage.blockchain.len() as u64,
+            timestamp: 0,
+            proposer_id: 1,
+            round: 0,
+            previous_block_hash: storage.tip_hash(),
+            transactions_root: Block::transactions_root(&data),
+            state_root: storage
+                .preview_state_root(&data, &proposer.public_key)
+                .unwrap_or_default(),
+        };
+        Block::new(header, data, &proposer.public_key, &proposer.private_key).unwrap()
+    }
+
+    fn definition(issuer_id: u32, max_supply: Option<u128>) -> AssetDefinition {
+        AssetDefinition {
+            name: "Currency".to_string(),
+            decimals: 0,
+            issuer_id,
+            max_supply: max_supply.map(Amount::new),
+            transferable: true,
+            redeemable: true,
+        }
+    }
+
+    // alice (счет 1) — эмитент "currency" со 100 единицами, у bob (счет 2) остатка нет.
+    fn storage_with_funds(alice: &mut User, bob: &mut User) -> Storage {
+        let mut storage = Storage::new();
+        storage
+            .add_block(block(
+                &storage,
+                vec![
+                    alice.create_account(),
+                    bob.create_account(),
+                    alice.sign(Command::RegisterAsset {
+                        asset_id: "currency".to_string(),
+                        definition: definition(1, None),
+                    }),
+                    alice.sign(Command::IssueAsset {
+                        account_id: 1,
+                        asset_id: "currency".to_string(),
+                        value: Decimal::new(100, 0),
+                    }),
+                ],
+            ))
+            .unwrap();
+        storage
+    }
+
+    // Пустой временный каталог для хранилища на диске.
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("storage-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        dir
+    }
+
+    // Хранилище в dir с count блоками; в первом блоке создается счет.
+    fn storage_on_disk(dir: &Path, count: usize) -> Storage {
+        let mut storage = Storage::open(dir, Params::default()).unwrap();
+        let mut alice = User::new();
+        storage
+            .add_block(block(&storage, vec![alice.create_account()]))
+            .unwrap();
+        while storage.blockchain.len() < count {
+            storage.add_block(block(&storage, vec![])).unwrap();
+        }
+        storage
+    }
+
+    #[test]
+    // проверяет, что после повторного открытия цепочка и состояние восстанавливаются из журнала.
+    fn test_log_is_replayed_on_open() {
+        let dir = temp_dir("replay");
+        let (tip, root) = {
+            let storage = storage_on_disk(&dir, 3);
+            (storage.tip_hash(), storage.state_root())
+        };
+        let mut storage = Storage::open(&dir, Params::default()).unwrap();
+        assert_eq!(storage.blockchain.len(), 3);
+        assert_eq!(storage.tip_hash(), tip);
+        assert_eq!(storage.state_root(), root);
+        assert_eq!(storage.state.accounts.len(), 1);
+
+        storage.add_block(block(&storage, vec![])).unwrap();
+        drop(storage);
+        let storage = Storage::open(&dir, Params::default()).unwrap();
+        assert_eq!(storage.blockchain.len(), 4);
+        assert!(storage.verify_chain().is_ok());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    // проверяет, что оборванная последняя запись и мусор в конце журнала отрезаются,
+    // а подтвержденные блоки сохраняются.
+    fn test_torn_tail_is_truncated() {
+        let dir = temp_dir("torn");
+        let path = dir.join(BLOCK_LOG_FILE);
+        let tip = storage_on_disk(&dir, 2).tip_hash();
+        let log = fs::read(&path).unwrap();
+
+        // Первая половина записи следующего блока.
+        let next = {
+            let storage = Storage::open(&dir, Params::default()).unwrap();
+            block(&storage, vec![]).serialize()
+        };
+        let mut record = (next.len() as u32).to_be_bytes().to_vec();
+        record.extend_from_slice(&Blake2s256::digest(&next));
+        record.extend_from_slice(&next);
+        for tail in [&record[..record.len() / 2], &[0xff; 7][..]] {
+            let mut torn = log.clone();
+            torn.extend_from_slice(tail);
+            fs::write(&path, &torn).unwrap();
+
+            let mut storage = Storage::open(&dir, Params::default()).unwrap();
+            assert_eq!(storage.blockchain.len(), 2);
+            assert_eq!(storage.tip_hash(), tip);
+            assert_eq!(fs::read(&path).unwrap(), log);
+
+            storage.add_block(block(&storage, vec![])).unwrap();
+            drop(storage);
+            assert_eq!(
+                Storage::open(&dir, Params::default())
+                    .unwrap()
+                    .blockchain
+                    .len(),
+                3
+            );
+            fs::write(&path, &log).unwrap();
+        }
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    // проверяет, что испорченная длина записи в середине журнала — ошибка, а не обрыв:
+    // блоки после нее не удаляются.
+    fn test_corrupted_length_in_the_middle_is_reported() {
+        let dir = temp_dir("corrupted");
+        let path = dir.join(BLOCK_LOG_FILE);
+        drop(storage_on_disk(&dir, 3));
+        let mut log = fs::read(&path).unwrap();
+        log[..4].copy_from_slice(&u32::MAX.to_be_bytes());
+        fs::write(&path, &log).unwrap();
+
+        assert!(matches!(
+            Storage::open(&dir, Params::default()),
+            Err(StorageError::CorruptedLog { offset: 0, .. })
+        ));
+        assert_eq!(fs::read(&path).unwrap(), log);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    // проверяет, что при открытии состояние берется из снимка и переигрываются только
+    // блоки после него, а снимок, не сходящийся с заголовком блока, удаляется.
+    fn test_snapshot_is_loaded_and_corrupted_one_is_discarded() {
+        let dir = temp_dir("snapshot");
+        let mut alice = User::new();
+        let mut storage = Storage::open(&dir, Params::default()).unwrap();
+        let data = vec![
+            alice.create_account(),
+            alice.sign(Command::RegisterAsset {
+                asset_id: "currency".to_string(),
+                definition: definition(1, None),
+            }),
+        ];
+        storage.add_block(block_by(&storage, &alice, data)).unwrap();
+        while storage.blockchain.len() < 3 {
+            storage
+                .add_block(block_by(&storage, &alice, vec![]))
+                .unwrap();
+        }
+        storage.write_snapshot().unwrap();
+        storage
+            .add_block(block_by(&storage, &alice, vec![]))
+            .unwrap();
+        drop(storage);
+        let snapshot_path = dir.join(Snapshot::file_name(3));
+        assert!(snapshot_path.exists());
+
+        // С наградой за блок видно, какие блоки выполнялись заново: только четвертый.
+        let reward = Params {
+            block_reward: Decimal::new(1, 0),
+            treasury: None,
+        };
+        let currency = (1, "currency".to_string());
+        let storage = Storage::open(&dir, reward.clone()).unwrap();
+        assert_eq!(storage.blockchain.len(), 4);
+        assert_eq!(storage.state.assets[&currency].value, Amount::new(1));
+
+        // Снимок с подмененным состоянием и согласованным с ним собственным корнем.
+        let mut forged = Snapshot::read(&snapshot_path).unwrap();
+        forged.state.accounts.get_mut(&1).unwrap().name = Some("Mallory".to_string());
+        forged.state_root = crypto::state_root(&forged.state);
+        fs::write(&snapshot_path, encode_versioned(&forged)).unwrap();
+        let storage = Storage::open(&dir, reward.clone()).unwrap();
+        assert!(!snapshot_path.exists());
+        assert_eq!(storage.state.accounts[&1].name, None);
+        assert_eq!(storage.state.assets[&currency].value, Amount::new(4));
+
+        fs::write(&snapshot_path, b"garbage").unwrap();
+        let storage = Storage::open(&dir, reward).unwrap();
+        assert!(!snapshot_path.exists());
+        assert_eq!(storage.blockchain.len(), 4);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    // проверяет, что при ошибке в третьей транзакции состояние не меняется и блок не добавляется.
+    fn test_block_with_failing_third_transaction_is_rolled_back() {
+        let (mut alice, mut bob) = (User::new(), User::new());
+        let mut storage = storage_with_funds(&mut alice, &mut bob);
+        let accounts_before = format!("{:?}", storage.state.accounts);
+        let root_before = storage.state_root();
+
+        let result = storage.add_block(block(
+            &storage,
+            vec![
+                alice.sign(Command::TransferFunds {
+                    from_account_id: 1,
+                    to_account_id: 2,
+                    value: Decimal::new(30, 0),
+                    asset_id: "currency".to_string(),
+                }),
+                alice.sign(Command::UpdateAccount {
+                    account_id: 1,
+                    name: Some("Alice".to_string()),
+                    contact_info: None,
+                }),
+                alice.sign(Command::TransferFunds {
+                    from_account_id: 1,
+                    to_account_id: 2,
+                    value: Decimal::new(1000, 0),
+                    asset_id: "currency".to_string(),
+                }),
+            ],
+        ));
+
+        assert!(matches!(
+            result,
+            Err(StorageError::CommandExecutionError(_))
+        ));
+        assert_eq!(storage.blockchain.len(), 1);
+        assert_eq!(storage.state_root(), root_before);
+        assert_eq!(format!("{:?}", storage.state.accounts), accounts_before);
+        assert_eq!(
+            storage.state.assets[&(1, "currency".to_string())].value,
+            Amount::new(100)
+        );
+        assert!(!storage
+            .state
+            .assets
+            .contains_key(&(2, "currency".to_string())));
+    }
+
+    #[test]
+    // проверяет, что неудачная транзакция среди кандидатов отбрасывается, не влияя
+    // на выполнение следующих, и отобранные транзакции составляют корректный блок.
+    fn test_select_executable_skips_failing_transaction() {
+        let (mut alice, mut bob) = (User::new(), User::new());
+        let mut storage = storage_with_funds(&mut alice, &mut bob);
+        let transfer = |user: &mut User, from_account_id, value| {
+            user.sign(Command::TransferFunds {
+                from_account_id,
+                to_account_id: if from_account_id == 1 { 2 } else { 1 },
+                value: Decimal::new(value, 0),
+                asset_id: "currency".to_string(),
+            })
+        };
+        let candidates = vec![
+            transfer(&mut alice, 1, 30),
+            transfer(&mut bob, 2, 1000),
+            transfer(&mut alice, 1, 20),
+        ];
+
+        let (data, rejected) = storage.select_executable(candidates);
+        assert_eq!(data.len(), 2);
+        assert_eq!(rejected.len(), 1);
+        assert!(matches!(
+            rejected[0].1,
+            BlockchainError::InsufficientBalance
+        ));
+        storage.add_block(block(&storage, data)).unwrap();
+        assert_eq!(
+            storage.state.assets[&(2, "currency".to_string())].value,
+            Amount::new(50)
+        );
+    }
+
+    #[test]
+    // проверяет, что перевод создает запись актива у нового счета, но не у несуществующего.
+    fn test_transfer_to_fresh_account_creates_holding() {
+        let (mut alice, mut bob) = (User::new(), User::new());
+        let mut storage = storage_with_funds(&mut alice, &mut bob);
+        let mut carol = User::new();
+        let transfer = |to_account_id| Command::TransferFunds {
+            from_account_id: 1,
+            to_account_id,
+            value: Decimal::new(25, 0),
+            asset_id: "currency".to_string(),
+        };
+
+        storage
+            .add_block(block(
+                &storage,
+                vec![carol.create_account(), alice.sign(transfer(3))],
+            ))
+            .unwrap();
+        let holding = &storage.state.assets[&(3, "currency".to_string())];
+        assert_eq!((holding.value, holding.owner_id), (Amount::new(25), 3));
+        assert_eq!(
+            storage.state.assets[&(1, "currency".to_string())].value,
+            Amount::new(75)
+        );
+
+        let missing = alice.sign(transfer(4));
+        assert!(matches!(
+            storage.add_block(block(&storage, vec![missing])),
+            Err(StorageError::CommandExecutionError(err)) if err == "account not found"
+        ));
+        assert!(!storage
+            .state
+            .assets
+            .contains_key(&(4, "currency".to_string())));
+    }
+
+    #[test]
+    // проверяет, что блок с тремя успешными транзакциями применяется целиком.
+    fn test_block_with_successful_transactions_is_applied() {
+        let (mut alice, mut bob) = (User::new(), User::new());
+        let mut storage = storage_with_funds(&mut alice, &mut bob);
+
+        storage
+            .add_block(block(
+                &storage,
+                vec![
+                    alice.sign(Command::TransferFunds {
+                        from_account_id: 1,
+                        to_account_id: 2,
+                        value: Decimal::new(30, 0),
+                        asset_id: "currency".to_string(),
+                    }),
+                    alice.sign(Command::UpdateAccount {
+                        account_id: 1,
+                        name: Some("Alice".to_string()),
+                        contact_info: None,
+                    }),
+                    bob.sign(Command::TransferFunds {
+                        from_account_id: 2,
+                        to_account_id: 1,
+                        value: Decimal::new(10, 0),
+                        asset_id: "currency".to_string(),
+                    }),
+                ],
+            ))
+            .unwrap();
+
+        assert_eq!(storage.blockchain.len(), 2);
+        assert_eq!(storage.state.accounts[&1].name.as_deref(), Some("Alice"));
+        assert_eq!(storage.state.accounts[&1].nonce, 4);
+        assert_eq!(
+            storage.state.assets[&(1, "currency".to_string())].value,
+            Amount::new(80)
+        );
+        assert_eq!(
+            storage.state.assets[&(2, "currency".to_string())].value,
+            Amount::new(20)
+        );
+    }
+
+    #[test]
+    // проверяет, что повтор транзакции с тем же nonce и чужая подпись отклоняются.
+    fn test_replayed_and_foreign_transactions_are_rejected() {
+        let (mut alice, mut bob) = (User::new(), User::new());
+        let mut storage = storage_with_funds(&mut alice, &mut bob);
+
+        let transfer = alice.sign(Command::TransferFunds {
+            from_account_id: 1,
+            to_account_id: 2,
+            value: Decimal::new(10, 0),
+            asset_id: "currency".to_string(),
+        });
+        storage
+            .add_block(block(&storage, vec![transfer.clone()]))
+            .unwrap();
+        assert!(storage.add_block(block(&storage, vec![transfer])).is_err());
+
+        let theft = bob.sign(Command::TransferFunds {
+            from_account_id: 1,
+            to_account_id: 2,
+            value: Decimal::new(10, 0),
+            asset_id: "currency".to_string(),
+        });
+        assert!(storage.add_block(block(&storage, vec![theft])).is_err());
+        assert_eq!(
+            storage.state.assets[&(1, "currency".to_string())].value,
+            Amount::new(90)
+        );
+    }
+
+    #[test]
+    // проверяет, что команды над чужим счетом отклоняются с ошибкой Unauthorized.
+    fn test_commands_on_foreign_accounts_are_unauthorized() {
+        let (mut alice, mut bob) = (User::new(), User::new());
+        let mut storage = storage_with_funds(&mut alice, &mut bob);
+
+        let mut state = storage.state.clone();
+        let context = ExecutionContext {
+            signer: bob.public_key.to_string(),
+            treasury: None,
+        };
+        for command in [
+            Command::UpdateAccount {
+                account_id: 1,
+                name: Some("Mallory".to_string()),
+                contact_info: None,
+            },
+            Command::IssueAsset {
+                account_id: 1,
+                asset_id: "currency".to_string(),
+                value: Decimal::new(5, 0),
+            },
+            Command::CreateAccount {
+                public_key: alice.public_key.to_string(),
+            },
+        ] {
+            assert!(matches!(
+                command.execute(&context, &mut state),
+                Err(BlockchainError::Unauthorized)
+            ));
+        }
+
+        let update = bob.sign(Command::UpdateAccount {
+            account_id: 2,
+            name: Some("Bob".to_string()),
+            contact_info: None,
+        });
+        storage.add_block(block(&storage, vec![update])).unwrap();
+        assert_eq!(storage.state.accounts[&2].name.as_deref(), Some("Bob"));
+    }
+
+    #[test]
+    // проверяет, что блок не от вершины отклоняется, а verify_chain находит первое разорванное звено.
+    fn test_chain_linkage_is_validated() {
+        let (mut alice, mut bob) = (User::new(), User::new());
+        let mut storage = storage_with_funds(&mut alice, &mut bob);
+        let stale = block(&storage, vec![]);
+        storage.add_block(block(&storage, vec![])).unwrap();
+        storage.add_block(block(&storage, vec![])).unwrap();
+
+        assert!(matches!(
+            storage.add_block(stale),
+            Err(StorageError::InvalidParent)
+        ));
+        assert_eq!(storage.blockchain.len(), 3);
+        assert!(storage.verify_chain().is_ok());
+
+        storage.blockchain[1]
+            .data
+            .push(alice.sign(Command::UpdateAccount {
+                account_id: 1,
+                name: None,
+                contact_info: None,
+            }));
+        assert!(matches!(
+            storage.verify_chain(),
+            Err(StorageError::BrokenChain { height: 1, .. })
+        ));
+    }
+
+    #[test]
+    // проверяет, что доказательство включения транзакции сходится с корнем из заголовка блока.
+    fn test_transaction_proof_verifies_against_header() {
+        let (mut alice, mut bob) = (User::new(), User::new());
+        let storage = storage_with_funds(&mut alice, &mut bob);
+        let block = &storage.blockchain[0];
+
+        for (index, transaction) in block.data.iter().enumerate() {
+            let proof = storage.transaction_proof(0, index).unwrap();
+            assert!(Client::verify_transaction_inclusion(
+                &block.header,
+                transaction,
+                &proof
+            ));
+            let other = &block.data[(index + 1) % block.data.len()];
+            assert!(!Client::verify_transaction_inclusion(
+                &block.header,
+                other,
+                &proof
+            ));
+        }
+        assert!(matches!(
+            storage.transaction_proof(0, block.data.len()),
+            Err(StorageError::TransactionNotFound { .. })
+        ));
+    }
+
+    #[test]
+    // проверяет доказательства остатка и отсутствия актива относительно корня состояния заголовка.
+    fn test_balance_proof_verifies_against_header() {
+        let (mut alice, mut bob) = (User::new(), User::new());
+        let storage = storage_with_funds(&mut alice, &mut bob);
+        let header = &storage.blockchain[0].header;
+
+        let proof = storage.prove_balance(1, "currency").unwrap();
+        assert_eq!(proof.asset.as_ref().unwrap().value, Amount::new(100));
+        assert!(Client::verify_balance(header, 1, "currency", &proof));
+        assert!(!Client::verify_balance(header, 2, "currency", &proof));
+
+        let mut forged = proof.clone();
+        forged.asset.as_mut().unwrap().value = Amount::new(1_000);
+        assert!(!Client::verify_balance(header, 1, "currency", &forged));
+
+        let absent = storage.prove_balance(1, "gold").unwrap();
+        assert!(absent.asset.is_none());
+        assert!(Client::verify_balance(header, 1, "gold", &absent));
+    }
+
+    #[test]
+    // проверяет, что корень дерева, обновляемого по изменениям блоков, совпадает
+    // с корнем дерева, построенного заново, в том числе при откате изменений.
+    fn test_incremental_state_root_matches_rebuild() {
+        let (mut alice, mut bob) = (User::new(), User::new());
+        let mut storage = storage_with_funds(&mut alice, &mut bob);
+        let before = storage.state.clone();
+        for value in 1..=5 {
+            let mut carol = User::new();
+            let data = vec![
+                carol.create_account(),
+                alice.sign(Command::TransferFunds {
+                    from_account_id: 1,
+                    to_account_id: storage.state.accounts.len() as u32 + 1,
+                    value: Decimal::new(value, 0),
+                    asset_id: "currency".to_string(),
+                }),
+                alice.sign(Command::IssueAsset {
+                    account_id: 1,
+                    asset_id: "currency".to_string(),
+                    value: Decimal::new(value, 0),
+                }),
+            ];
+            storage.add_block(block(&storage, data)).unwrap();
+            assert_eq!(storage.state_root(), crypto::state_root(&storage.state));
+        }
+
+        let mut tree = storage.tree.clone();
+        tree.update(&storage.state, &before);
+        assert_eq!(tree.root(), crypto::state_root(&before));
+    }
+
+    #[test]
+    // проверяет, что стейк меняет набор валидаторов на границе эпохи, а выведенный стейк
+    // возвращается на счет в конце следующей эпохи.
+    fn test_staking_takes_effect_at_epoch_boundary() {
+        let (mut alice, mut bob) = (User::new(), User::new());
+        let mut storage = storage_with_funds(&mut alice, &mut bob);
+        let validator = Identity::generate();
+        let currency = (1, "currency".to_string());
+
+        let bond = alice.sign(Command::Bond {
+            account_id: 1,
+            public_key: to_hex(&validator.public_key.0),
+            value: Decimal::new(40, 0),
+            key_signature: validator.sign_bond(1),
+        });
+        storage.add_block(block(&storage, vec![bond])).unwrap();
+        assert_eq!(storage.state.assets[&currency].value, Amount::new(60));
+        assert!(storage.state.staking.active.is_empty());
+
+        while storage.blockchain.len() < EPOCH_LENGTH as usize {
+            storage.add_block(block(&storage, vec![])).unwrap();
+        }
+        let validators = storage.state.staking.validator_set();
+        assert_eq!(validators.stake_of(&validator.public_key), 40);
+        assert_eq!(validators.total_stake(), 40);
+
+        let unbond = alice.sign(Command::Unbond {
+            account_id: 1,
+            validator_id: 1,
+            value: Decimal::new(15, 0),
+        });
+        storage.add_block(block(&storage, vec![unbond])).unwrap();
+        assert_eq!(storage.state.assets[&currency].value, Amount::new(60));
+        while storage.blockchain.len() < 2 * EPOCH_LENGTH as usize {
+            storage.add_block(block(&storage, vec![])).unwrap();
+        }
+        assert_eq!(storage.state.assets[&currency].value, Amount::new(60));
+        while storage.blockchain.len() < 3 * EPOCH_LENGTH as usize {
+            storage.add_block(block(&storage, vec![])).unwrap();
+        }
+        assert_eq!(storage.state.assets[&currency].value, Amount::new(75));
+        assert_eq!(
+            storage
+                .state
+                .staking
+                .validator_set()
+                .stake_of(&validator.public_key),
+            25
+        );
+
+        let context = ExecutionContext {
+            signer: bob.public_key.to_string(),
+            treasury: None,
+        };
+        let mut state = storage.state.clone();
+        assert!(matches!(
+            Command::Delegate {
+                account_id: 2,
+                validator_id: 2,
+                value: Decimal::new(1, 0),
+            }
+            .execute(&context, &mut state),
+            Err(BlockchainError::ValidatorNotFound)
+        ));
+        assert!(matches!(
+            Command::Delegate {
+                account_id: 2,
+                validator_id: 1,
+                value: Decimal::new(0, 0),
+            }
+            .execute(&context, &mut state),
+            Err(BlockchainError::InvalidAmount)
+        ));
+    }
+
+    #[test]
+    // проверяет, что ключ валидатора сравнивается без учета регистра hex, а регистрация
+    // требует подписи самим ключом для того же счета.
+    fn test_bond_requires_key_ownership() {
+        let (mut alice, mut bob) = (User::new(), User::new());
+        let mut storage = storage_with_funds(&mut alice, &mut bob);
+        let (validator, other) = (Identity::generate(), Identity::generate());
+        let bond = |account_id, public_key: String, key_signature| Command::Bond {
+            account_id,
+            public_key,
+            value: Decimal::new(1, 0),
+            key_signature,
+        };
+
+        let upper = to_hex(&validator.public_key.0).to_uppercase();
+        let transaction = alice.sign(bond(1, upper.clone(), validator.sign_bond(1)));
+        storage
+            .add_block(block(&storage, vec![transaction]))
+            .unwrap();
+        assert_eq!(
+            storage.state.staking.validators[&1],
+            to_hex(&validator.public_key.0)
+        );
+
+        let context = ExecutionContext {
+            signer: bob.public_key.to_string(),
+            treasury: None,
+        };
+        let mut state = storage.state.clone();
+        assert!(matches!(
+            bond(2, upper.to_lowercase(), validator.sign_bond(2)).execute(&context, &mut state),
+            Err(BlockchainError::ValidatorKeyInUse)
+        ));
+        let key = to_hex(&other.public_key.0);
+        assert!(matches!(
+            bond(2, key.clone(), other.sign_bond(1)).execute(&context, &mut state),
+            Err(BlockchainError::InvalidSignature)
+        ));
+        assert!(matches!(
+            bond(2, key, validator.sign_bond(2)).execute(&context, &mut state),
+            Err(BlockchainError::InvalidSignature)
+        ));
+        assert_eq!(state.staking.validators.len(), 1);
+    }
+
+    #[test]
+    // проверяет, что смена ключа валидатора вступает в силу только на границе эпохи,
+    // а прежний ключ до тех пор нельзя зарегистрировать на другой счет.
+    fn test_validator_key_change_waits_for_epoch_end() {
+        let (mut alice, mut bob) = (User::new(), User::new());
+        let mut storage = storage_with_funds(&mut alice, &mut bob);
+        let (old_key, new_key) = (Identity::generate(), Identity::generate());
+        let mut bond = |identity: &Identity, value| {
+            alice.sign(Command::Bond {
+                account_id: 1,
+                public_key: to_hex(&identity.public_key.0),
+                value: Decimal::new(value, 0),
+                key_signature: identity.sign_bond(1),
+            })
+        };
+
+        let transaction = bond(&old_key, 40);
+        storage
+            .add_block(block(&storage, vec![transaction]))
+            .unwrap();
+        while storage.blockchain.len() < EPOCH_LENGTH as usize {
+            storage.add_block(block(&storage, vec![])).unwrap();
+        }
+        let transaction = bond(&new_key, 1);
+        storage
+            .add_block(block(&storage, vec![transaction]))
+            .unwrap();
+        let validators = storage.state.staking.validator_set();
+        assert_eq!(validators.stake_of(&old_key.public_key), 40);
+        assert_eq!(validators.stake_of(&new_key.public_key), 0);
+
+        let context = ExecutionContext {
+            signer: bob.public_key.to_string(),
+            treasury: None,
+        };
+        assert!(matches!(
+            Command::Bond {
+                account_id: 2,
+                public_key: to_hex(&old_key.public_key.0),
+                value: Decimal::new(1, 0),
+                key_signature: old_key.sign_bond(2),
+            }
+            .execute(&context, &mut storage.state.clone()),
+            Err(BlockchainError::ValidatorKeyInUse)
+        ));
+
+        while storage.blockchain.len() < 2 * EPOCH_LENGTH as usize {
+            storage.add_block(block(&storage, vec![])).unwrap();
+        }
+        let validators = storage.state.staking.validator_set();
+        assert_eq!(validators.stake_of(&old_key.public_key), 0);
+        assert_eq!(validators.stake_of(&new_key.public_key), 41);
+    }
+
+    #[test]
+    // проверяет, что наказание сжигает и выведенный, но еще не возвращенный стейк,
+    // а повторное и устаревшее доказательства отклоняются.
+    fn test_evidence_is_processed_once_and_reaches_unbonding_stake() {
+        let (mut alice, mut bob) = (User::new(), User::new());
+        let mut storage = storage_with_funds(&mut alice, &mut bob);
+        let validator = Identity::generate();
+        let evidence = |height| {
+            let signed = |timestamp| {
+                let header = BlockHeader {
+                    height,
+                    timestamp,
+                    proposer_id: 1,
+                    round: 0,
+                    previous_block_hash: None,
+                    transactions_root: Block::transactions_root(&[]),
+                    state_root: Vec::new(),
+                };
+                Block::new(
+                    header,
+                    vec![],
+                    &validator.public_key,
+                    &validator.private_key,
+                )
+                .unwrap()
+                .signed_header()
+            };
+            Command::ReportEquivocation {
+                evidence: Box::new(Equivocation {
+                    first: signed(1),
+                    second: signed(2),
+                }),
+            }
+        };
+        let bond = alice.sign(Command::Bond {
+            account_id: 1,
+            public_key: to_hex(&validator.public_key.0),
+            value: Decimal::new(40, 0),
+            key_signature: validator.sign_bond(1),
+        });
+        storage.add_block(block(&storage, vec![bond])).unwrap();
+        while storage.blockchain.len() < EPOCH_LENGTH as usize {
+            storage.add_block(block(&storage, vec![])).unwrap();
+        }
+        let unbond = alice.sign(Command::Unbond {
+            account_id: 1,
+            validator_id: 1,
+            value: Decimal::new(15, 0),
+        });
+        // Доказательство не относится к счету и подписывается с nonce 0.
+        let report = User::new().sign(evidence(10));
+        storage
+            .add_block(block(&storage, vec![unbond, report]))
+            .unwrap();
+        let staking = &storage.state.staking;
+        assert!(staking.bonds.is_empty() && staking.unbonding.is_empty());
+        assert_eq!(storage.state.total_supply("currency"), Amount::new(60));
+
+        let context = ExecutionContext {
+            signer: bob.public_key.to_string(),
+            treasury: None,
+        };
+        assert!(matches!(
+            evidence(10).execute(&context, &mut storage.state.clone()),
+            Err(BlockchainError::EvidenceAlreadyProcessed)
+        ));
+        while storage.blockchain.len() < 2 * EPOCH_LENGTH as usize {
+            storage.add_block(block(&storage, vec![])).unwrap();
+        }
+        assert!(matches!(
+            evidence(5).execute(&context, &mut storage.state.clone()),
+            Err(BlockchainError::EvidenceExpired)
+        ));
+        while storage.blockchain.len() < 3 * EPOCH_LENGTH as usize {
+            storage.add_block(block(&storage, vec![])).unwrap();
+        }
+        assert!(storage.state.staking.evidence.is_empty());
+    }
+
+    #[test]
+    // проверяет, что стейк больше u64::MAX не принимается, а не обрезается: иначе один
+    // из двух таких валидаторов в одиночку набирал бы кворум.
+    fn test_stake_above_consensus_limit_is_rejected() {
+        let (mut alice, mut bob) = (User::new(), User::new());
+        let mut storage = storage_with_funds(&mut alice, &mut bob);
+        let large = u128::from(u64::MAX) + 1;
+        let data = vec![
+            alice.sign(Command::IssueAsset {
+                account_id: 1,
+                asset_id: "currency".to_string(),
+                value: Decimal::new(2 * large, 0),
+            }),
+            alice.sign(Command::TransferFunds {
+                from_account_id: 1,
+                to_account_id: 2,
+                value: Decimal::new(large, 0),
+                asset_id: "currency".to_string(),
+            }),
+        ];
+        storage.add_block(block(&storage, data)).unwrap();
+        let (first, second) = (Identity::generate(), Identity::generate());
+        let bond = |account_id, validator: &Identity, value| Command::Bond {
+            account_id,
+            public_key: to_hex(&validator.public_key.0),
+            value: Decimal::new(value, 0),
+            key_signature: validator.sign_bond(account_id),
+        };
+        let context = |user: &User| ExecutionContext {
+            signer: user.public_key.to_string(),
+            treasury: None,
+        };
+
+        assert!(matches!(
+            bond(1, &first, large).execute(&context(&alice), &mut storage.state.clone()),
+            Err(BlockchainError::StakeLimitExceeded)
+        ));
+        let data = vec![
+            alice.sign(bond(1, &first, large / 2)),
+            bob.sign(bond(2, &second, large / 2 - 1)),
+        ];
+        storage.add_block(block(&storage, data)).unwrap();
+        assert!(matches!(
+            bond(2, &second, 1).execute(&context(&bob), &mut storage.state.clone()),
+            Err(BlockchainError::StakeLimitExceeded)
+        ));
+
+        while storage.blockchain.len() < EPOCH_LENGTH as usize {
+            storage.add_block(block(&storage, vec![])).unwrap();
+        }
+        let validators = storage.state.staking.validator_set();
+        assert_eq!(validators.total_stake(), u128::from(u64::MAX));
+        assert!(!validators.is_quorum(u128::from(validators.stake_of(&first.public_key))));
+    }
+
+    // Масса "currency" на счетах и в стейкинге.
+    fn circulating(state: &State) -> Amount {
+        state
+            .assets
+            .iter()
+            .filter(|((_, asset_id), _)| asset_id == "currency")
+            .map(|(_, asset)| &asset.value)
+            .chain(state.staking.bonds.values())
+            .chain(state.staking.unbonding.values())
+            .chain(state.staking.releasing.values())
+            .fold(Amount::ZERO, |total, value| {
+                total.checked_add(*value).unwrap()
+            })
+    }
+
+    #[test]
+    // проверяет, что автор блока получает комиссии и награду, а учет массы сходится с остатками.
+    fn test_proposer_receives_fees_and_reward() {
+        let (mut alice, mut bob) = (User::new(), User::new());
+        let mut storage = storage_with_funds(&mut alice, &mut bob);
+        storage.params.block_reward = Decimal::new(5, 0);
+
+        let commission = alice.sign(Command::TransactionCommission {
+            account_id: 1,
+            value: Decimal::new(3, 0),
+        });
+        storage
+            .add_block(block_by(&storage, &bob, vec![commission]))
+            .unwrap();
+        assert_eq!(
+            storage.state.assets[&(1, "currency".to_string())].value,
+            Amount::new(97)
+        );
+        assert_eq!(
+            storage.state.assets[&(2, "currency".to_string())].value,
+            Amount::new(8)
+        );
+        assert_eq!(storage.state.total_supply("currency"), Amount::new(105));
+
+        // У автора без счета комиссия сгорает, а награда не выпускается.
+        let commission = alice.sign(Command::TransactionCommission {
+            account_id: 1,
+            value: Decimal::new(2, 0),
+        });
+        storage
+            .add_block(block(&storage, vec![commission]))
+            .unwrap();
+        let validator = Identity::generate();
+        let bond = alice.sign(Command::Bond {
+            account_id: 1,
+            public_key: to_hex(&validator.public_key.0),
+            value: Decimal::new(10, 0),
+            key_signature: validator.sign_bond(1),
+        });
+        storage
+            .add_block(block_by(&storage, &bob, vec![bond]))
+            .unwrap();
+        assert_eq!(storage.state.supply["currency"].issued, Amount::new(110));
+        assert_eq!(storage.state.supply["currency"].burned, Amount::new(2));
+        assert_eq!(
+            circulating(&storage.state),
+            storage.state.total_supply("currency")
+        );
+    }
+
+    #[test]
+    // проверяет, что с казначейством "currency" зарегистрирована с начала цепочки,
+    // казначейство может ее выпускать, а награда считается в ее единицах;
+    // без зарегистрированной "currency" награда не выпускается.
+    fn test_genesis_currency_is_issued_by_treasury() {
+        let mut alice = User::new();
+        let mut storage = Storage::with_params(Params {
+            block_reward: Decimal::new(15, 1),
+            treasury: Some(1),
+        });
+        let currency = storage.state.definition("currency").unwrap();
+        assert_eq!(currency.issuer_id, 1);
+        assert_eq!(currency.decimals, CURRENCY_DECIMALS);
+
+        let data = vec![
+            alice.create_account(),
+            alice.sign(Command::AddFunds {
+                account_id: 1,
+                value: Decimal::new(1025, 2),
+                asset_id: "currency".to_string(),
+            }),
+        ];
+        storage.add_block(block_by(&storage, &alice, data)).unwrap();
+        assert_eq!(
+            storage.state.assets[&(1, "currency".to_string())].value,
+            Amount::new(1175)
+        );
+        assert_eq!(storage.state.total_supply("currency"), Amount::new(1175));
+
+        // Без казначейства актива нет, и награда не выпускается.
+        let mut storage = Storage::with_params(Params {
+            block_reward: Decimal::new(1, 0),
+            treasury: None,
+        });
+        let mut bob = User::new();
+        let data = vec![bob.create_account()];
+        storage.add_block(block_by(&storage, &bob, data)).unwrap();
+        assert!(storage.state.definitions.is_empty());
+        assert_eq!(storage.state.total_supply("currency"), Amount::ZERO);
+    }
+
+    #[test]
+    // проверяет, что награда за блок выпускается только до предельной массы "currency".
+    fn test_block_reward_respects_supply_cap() {
+        let mut alice = User::new();
+        let mut storage = Storage::new();
+        storage.params.block_reward = Decimal::new(3, 0);
+        let data = vec![
+            alice.create_account(),
+            alice.sign(Command::RegisterAsset {
+                asset_id: "currency".to_string(),
+                definition: definition(1, Some(104)),
+            }),
+            alice.sign(Command::IssueAsset {
+                account_id: 1,
+                asset_id: "currency".to_string(),
+                value: Decimal::new(100, 0),
+            }),
+        ];
+        storage.add_block(block_by(&storage, &alice, data)).unwrap();
+        assert_eq!(storage.state.total_supply("currency"), Amount::new(103));
+
+        for _ in 0..2 {
+            storage
+                .add_block(block_by(&storage, &alice, vec![]))
+                .unwrap();
+        }
+        assert_eq!(storage.state.total_supply("currency"), Amount::new(104));
+        assert_eq!(
+            storage.state.assets[&(1, "currency".to_string())].value,
+            Amount::new(104)
+        );
+    }
+
+    #[test]
+    // проверяет, что повторный AddFunds прибавляется к остатку, нулевой выпуск отклоняется,
+    // а выпускать может только эмитент актива или казначейство.
+    fn test_add_funds_accumulates_and_requires_issuer() {
+        let (mut alice, mut bob) = (User::new(), User::new());
+        let mut storage = storage_with_funds(&mut alice, &mut bob);
+        let add_funds = |user: &mut User, account_id, value| {
+            user.sign(Command::AddFunds {
+                account_id,
+                value: Decimal::new(value, 0),
+                asset_id: "currency".to_string(),
+            })
+        };
+
+        let first = add_funds(&mut alice, 1, 20);
+        let second = add_funds(&mut alice, 1, 30);
+        storage
+            .add_block(block(&storage, vec![first, second]))
+            .unwrap();
+        assert_eq!(
+            storage.state.assets[&(1, "currency".to_string())].value,
+            Amount::new(150)
+        );
+        assert_eq!(storage.state.total_supply("currency"), Amount::new(150));
+        let minted: Vec<_> = storage
+            .state
+            .mints
+            .iter()
+            .map(|mint| (mint.account_id, mint.value.units()))
+            .collect();
+        // Первая запись — выпуск IssueAsset при создании хранилища.
+        assert_eq!(minted, vec![(1, 100), (1, 20), (1, 30)]);
+
+        let context = ExecutionContext {
+            signer: alice.public_key.to_string(),
+            treasury: None,
+        };
+        let zero_mints = [
+            Command::AddFunds {
+                account_id: 1,
+                value: Decimal::new(0, 0),
+                asset_id: "currency".to_string(),
+            },
+            Command::IssueAsset {
+                account_id: 1,
+                asset_id: "currency".to_string(),
+                value: Decimal::new(0, 0),
+            },
+        ];
+        for command in zero_mints {
+            assert!(matches!(
+                command.execute(&context, &mut storage.state.clone()),
+                Err(BlockchainError::InvalidAmount)
+            ));
+        }
+
+        let forged = add_funds(&mut bob, 2, 1_000);
+        assert!(storage.add_block(block(&storage, vec![forged])).is_err());
+        assert!(!storage
+            .state
+            .assets
+            .contains_key(&(2, "currency".to_string())));
+
+        // Казначейство выпускает любой зарегистрированный актив, в том числе чужой.
+        storage.params.treasury = Some(2);
+        bob.nonce -= 1;
+        let register = alice.sign(Command::RegisterAsset {
+            asset_id: "gold".to_string(),
+            definition: definition(1, Some(10)),
+        });
+        let treasury_mint = bob.sign(Command::AddFunds {
+            account_id: 2,
+            value: Decimal::new(7, 0),
+            asset_id: "gold".to_string(),
+        });
+        storage
+            .add_block(block(&storage, vec![register, treasury_mint]))
+            .unwrap();
+        assert_eq!(
+            storage.state.assets[&(2, "gold".to_string())].value,
+            Amount::new(7)
+        );
+        assert_eq!(storage.state.mints.len(), 4);
+    }
+
+    #[test]
+    // проверяет, что выпуск, переводы и обмен актива ограничены его описанием в реестре.
+    fn test_asset_registry_enforces_definition() {
+        let (mut alice, mut bob) = (User::new(), User::new());
+        let storage = storage_with_funds(&mut alice, &mut bob);
+        let mut state = storage.state.clone();
+        let (as_alice, as_bob) = (
+            ExecutionContext {
+                signer: alice.public_key.to_string(),
+                treasury: None,
+            },
+            ExecutionContext {
+                signer: bob.public_key.to_string(),
+                treasury: None,
+            },
+        );
+        let ticket = AssetDefinition {
+            name: "Ticket".to_string(),
+            decimals: 0,
+            issuer_id: 1,
+            max_supply: Some(Amount::new(5)),
+            transferable: false,
+            redeemable: false,
+        };
+        let register = Command::RegisterAsset {
+            asset_id: "ticket".to_string(),
+            definition: ticket,
+        };
+        register.execute(&as_alice, &mut state).unwrap();
+        assert!(matches!(
+            register.execute(&as_alice, &mut state),
+            Err(BlockchainError::AssetExists)
+        ));
+
+        let issue = |account_id, asset_id: &str, value| Command::IssueAsset {
+            account_id,
+            asset_id: asset_id.to_string(),
+            value: Decimal::new(value, 0),
+        };
+        issue(1, "ticket", 5)
+            .execute(&as_alice, &mut state)
+            .unwrap();
+        assert!(matches!(
+            issue(1, "ticket", 1).execute(&as_alice, &mut state),
+            Err(BlockchainError::SupplyCapExceeded)
+        ));
+        assert!(matches!(
+            issue(2, "ticket", 1).execute(&as_bob, &mut state),
+            Err(BlockchainError::Unauthorized)
+        ));
+        assert!(matches!(
+            issue(1, "silver", 1).execute(&as_alice, &mut state),
+            Err(BlockchainError::UnknownAsset(_))
+        ));
+        assert!(matches!(
+            Command::TransferFunds {
+                from_account_id: 1,
+                to_account_id: 2,
+                value: Decimal::new(1, 0),
+                asset_id: "ticket".to_string(),
+            }
+            .execute(&as_alice, &mut state),
+            Err(BlockchainError::NotTransferable)
+        ));
+        assert!(matches!(
+            Command::RedeemAsset {
+                account_id: 1,
+                asset_id: "ticket".to_string(),
+                value: Decimal::new(1, 0),
+                redeem_in_asset_id: "currency".to_string(),
+            }
+            .execute(&as_alice, &mut state),
+            Err(BlockchainError::NotRedeemable)
+        ));
+        assert_eq!(state.total_supply("ticket"), Amount::new(5));
+        assert_eq!(state.total_supply("currency"), Amount::new(100));
+    }
+
+    #[test]
+    // проверяет, что обменом нельзя выпустить чужой актив, а эмитент целевого актива обменивать может.
+    fn test_redeem_requires_target_issuer() {
+        let (mut alice, mut bob) = (User::new(), User::new());
+        let storage = storage_with_funds(&mut alice, &mut bob);
+        let mut state = storage.state.clone();
+        let (as_alice, as_bob) = (
+            ExecutionContext {
+                signer: alice.public_key.to_string(),
+                treasury: None,
+            },
+            ExecutionContext {
+                signer: bob.public_key.to_string(),
+                treasury: None,
+            },
+        );
+        let redeemable = |issuer_id| AssetDefinition {
+            redeemable: true,
+            ..definition(issuer_id, None)
+        };
+        let redeem = |account_id, asset_id: &str, value| Command::RedeemAsset {
+            account_id,
+            asset_id: asset_id.to_string(),
+            value: Decimal::new(value, 0),
+            redeem_in_asset_id: "currency".to_string(),
+        };
+
+        Command::RegisterAsset {
+            asset_id: "junk".to_string(),
+            definition: redeemable(2),
+        }
+        .execute(&as_bob, &mut state)
+        .unwrap();
+        Command::IssueAsset {
+            account_id: 2,
+            asset_id: "junk".to_string(),
+            value: Decimal::new(1_000_000, 0),
+        }
+        .execute(&as_bob, &mut state)
+        .unwrap();
+        assert!(matches!(
+            redeem(2, "junk", 1_000_000).execute(&as_bob, &mut state),
+            Err(BlockchainError::Unauthorized)
+        ));
+        assert!(!state.assets.contains_key(&(2, "currency".to_string())));
+        assert_eq!(state.total_supply("currency"), Amount::new(100));
+
+        Command::RegisterAsset {
+            asset_id: "voucher".to_string(),
+            definition: redeemable(1),
+        }
+        .execute(&as_alice, &mut state)
+        .unwrap();
+        Command::IssueAsset {
+            account_id: 1,
+            asset_id: "voucher".to_string(),
+            value: Decimal::new(10, 0),
+        }
+        .execute(&as_alice, &mut state)
+        .unwrap();
+        redeem(1, "voucher", 4)
+            .execute(&as_alice, &mut state)
+            .unwrap();
+        assert_eq!(state.total_supply("voucher"), Amount::new(6));
+        assert_eq!(state.total_supply("currency"), Amount::new(104));
+    }
+
+    #[test]
+    // проверяет, что переполнение остатка и нулевой перевод отклоняются без изменения состояния.
+    fn test_amount_overflow_is_rejected() {
+        let (mut alice, mut bob) = (User::new(), User::new());
+        let storage = storage_with_funds(&mut alice, &mut bob);
+        let mut state = storage.state.clone();
+        let context = ExecutionContext {
+            signer: alice.public_key.to_string(),
+            treasury: None,
+        };
+        let register = Command::RegisterAsset {
+            asset_id: "points".to_string(),
+            definition: definition(1, None),
+        };
+        register.execute(&context, &mut state).unwrap();
+        let issue = |value| Command::IssueAsset {
+            account_id: 1,
+            asset_id: "points".to_string(),
+            value: Decimal::new(value, 0),
+        };
+        issue(u128::MAX).execute(&context, &mut state).unwrap();
+        assert!(matches!(
+            issue(1).execute(&context, &mut state),
+            Err(BlockchainError::Overflow)
+        ));
+        assert_eq!(state.total_supply("points"), Amount::MAX);
+
+        let transfer = |value| Command::TransferFunds {
+            from_account_id: 1,
+            to_account_id: 2,
+            value: Decimal::new(value, 0),
+            asset_id: "currency".to_string(),
+        };
+        assert!(matches!(
+            transfer(0).execute(&context, &mut state),
+            Err(BlockchainError::InvalidAmount)
+        ));
+        assert!(matches!(
+            transfer(101).execute(&context, &mut state),
+            Err(BlockchainError::InsufficientBalance)
+        ));
+        assert_eq!(
+            state.assets[&(1, "currency".to_string())].value,
+            Amount::new(100)
+        );
+    }
+
+    #[test]
+    // проверяет на случайных последовательностях переводов, что сумма остатков
+    // всегда равна массе актива.
+    fn test_transfers_conserve_supply() {
+        let (mut alice, mut bob) = (User::new(), User::new());
+        let storage = storage_with_funds(&mut alice, &mut bob);
+        let signers = [alice.public_key.to_string(), bob.public_key.to_string()];
+        // Детерминированный генератор splitmix64, чтобы падение воспроизводилось.
+        let mut seed: u64 = 0x5eed;
+        let mut next = move || {
+            seed = seed.wrapping_add(0x9e37_79b9_7f4a_7c15);
+            let mut z = seed;
+            z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+            z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+            z ^ (z >> 31)
+        };
+        for _ in 0..20 {
+            let mut state = storage.state.clone();
+            for _ in 0..50 {
+                let from = (next() % 2) as usize;
+                let context = ExecutionContext {
+                    signer: signers[from].clone(),
+                    treasury: None,
+                };
+                let command = Command::TransferFunds {
+                    from_account_id: from as u32 + 1,
+                    to_account_id: (next() % 2) as u32 + 1,
+                    value: Decimal::new(u128::from(next() % 120), 0),
+                    asset_id: "currency".to_string(),
+                };
+                let mut next_state = state.clone();
+                if command.execute(&context, &mut next_state).is_ok() {
+                    state = next_state;
+                }
+                assert_eq!(circulating(&state), state.total_supply("currency"));
+            }
+        }
+    }
+
+    #[test]
+    // проверяет, что десятичные суммы переводятся в единицы актива по его числу знаков,
+    // а суммы с лишними знаками после запятой отклоняются.
+    fn test_decimal_amounts_honour_asset_precision() {
+        let (mut alice, mut bob) = (User::new(), User::new());
+        let storage = storage_with_funds(&mut alice, &mut bob);
+        let mut state = storage.state.clone();
+        let context = ExecutionContext {
+            signer: alice.public_key.to_string(),
+            treasury: None,
+        };
+        let usd = |decimals| Command::RegisterAsset {
+            asset_id: "usd".to_string(),
+            definition: AssetDefinition {
+                decimals,
+                ..definition(1, None)
+            },
+        };
+        assert!(matches!(
+            usd(19).execute(&context, &mut state),
+            Err(BlockchainError::ExcessPrecision)
+        ));
+        usd(2).execute(&context, &mut state).unwrap();
+        let issue = |value: &str| Command::IssueAsset {
+            account_id: 1,
+            asset_id: "usd".to_string(),
+            value: value.parse().unwrap(),
+        };
+        issue("12.5").execute(&context, &mut state).unwrap();
+        issue("0.250").execute(&context, &mut state).unwrap();
+        assert!(matches!(
+            issue("0.005").execute(&context, &mut state),
+            Err(BlockchainError::ExcessPrecision)
+        ));
+        let usd = state.assets[&(1, "usd".to_string())].value;
+        assert_eq!(usd, Amount::new(1275));
+        assert_eq!(state.decimal("usd", usd).to_string(), "12.75");
+
+        assert_eq!("1.50".parse::<Decimal>().unwrap(), Decimal::new(15, 1));
+        assert_eq!("1.5".parse::<Decimal>().unwrap(), Decimal::new(150, 2));
+        for invalid in ["", "-1", "1.", ".5", "1.2.3", "1e3"] {
+            assert!(invalid.parse::<Decimal>().is_err(), "{}", invalid);
+        }
+    }
+}
@@ -0,0 +1,161 @@
+use std::str::FromStr;
+
+use ursa::keys::PublicKey;
+
+use crate::crypto::{hash_bytes, Hash};
+use crate::encoding::Encode;
+use crate::identity::{parse_public_key, IdentityError};
+
+#[derive(Debug, Clone)]
+pub struct Validator {
+    pub public_key: PublicKey,
+    pub stake: u64,
+}
+
+// Валидатор задается строкой "<открытый ключ в hex>:<стейк>".
+impl FromStr for Validator {
+    type Err = IdentityError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (public_key, stake) = s
+            .split_once(':')
+            .ok_or_else(|| IdentityError::InvalidPublicKey(s.to_string()))?;
+        Ok(Self {
+            public_key: parse_public_key(public_key)?,
+            stake: stake
+                .parse()
+                .map_err(|_| IdentityError::InvalidPublicKey(s.to_string()))?,
+        })
+    }
+}
+
+// Набор валидаторов: идентичности пиров, чьи блоки принимаются, и их стейки.
+#[derive(Debug, Clone, Default)]
+pub struct ValidatorSet {
+    validators: Vec<Validator>,
+}
+
+impl ValidatorSet {
+    // Повторяющиеся ключи объединяются; порядок фиксирован, чтобы у всех пиров он совпадал.
+    pub fn new(validators: Vec<Validator>) -> Self {
+        let mut set = Self::default();
+        for validator in validators {
+            match set
+                .validators
+                .iter_mut()
+                .find(|known| known.public_key == validator.public_key)
+            {
+                Some(known) => known.stake = known.stake.saturating_add(validator.stake),
+                None => set.validators.push(validator),
+            }
+        }
+        set.validators
+            .sort_by(|a, b| a.public_key.0.cmp(&b.public_key.0));
+        set
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Validator> {
+        self.validators.iter()
+    }
+
+    // Стейк валидатора; 0 для ключей вне набора.
+    pub fn stake_of(&self, public_key: &PublicKey) -> u64 {
+        self.validators
+            .iter()
+            .find(|validator| &validator.public_key == public_key)
+            .map_or(0, |validator| validator.stake)
+    }
+
+    // Общий стейк. Считается в u128, где сумма стейков u64 не переполняется.
+    pub fn total_stake(&self) -> u128 {
+        self.validators
+            .iter()
+            .map(|validator| u128::from(validator.stake))
+            .sum()
+    }
+
+    // Больше 2/3 общего стейка — достаточно для решения консенсуса.
+    pub fn is_quorum(&self, stake: u128) -> bool {
+        stake * 3 > self.total_stake() * 2
+    }
+
+    // Ведущий раунда: валидатор, выбранный с вероятностью, пропорциональной стейку,
+    // по хэшу от зерна (хэша родительского блока), высоты и номера раунда. Выбор
+    // детерминирован, поэтому любой пир может проверить, что автор блока был избран.
+    pub fn leader(&self, seed: &Hash, height: u64, round: u32) -> Option<&Validator> {
+        let total_stake = self.total_stake();
+        if total_stake == 0 {
+            return None;
+        }
+        let mut bytes = seed.clone();
+        height.encode(&mut bytes);
+        round.encode(&mut bytes);
+        let hash = hash_bytes(&bytes);
+        let mut random = [0u8; 16];
+        random.copy_from_slice(&hash[..16]);
+        // Смещение от взятия остатка пренебрежимо мало при 128-битном числе.
+        let mut point = u128::from_be_bytes(random) % total_stake;
+        for validator in &self.validators {
+            if point < u128::from(validator.stake) {
+                return Some(validator);
+            }
+            point -= u128::from(validator.stake);
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ursa::signatures::{prelude::Ed25519Sha512, SignatureScheme};
+
+    use super::*;
+
+    fn validator(stake: u64) -> Validator {
+        let (public_key, _) = Ed25519Sha512::new().keypair(None).unwrap();
+        Validator { public_key, stake }
+    }
+
+    #[test]
+    // проверяет, что ведущий определяется однозначно и одинаково для всех пиров.
+    fn test_leader_is_deterministic() {
+        let set = ValidatorSet::new(vec![validator(1), validator(2), validator(3)]);
+        let seed = vec![7u8; 32];
+        for round in 0..50 {
+            let leader = set.leader(&seed, 10, round).unwrap();
+            assert_eq!(
+                set.leader(&seed, 10, round).unwrap().public_key,
+                leader.public_key
+            );
+        }
+        assert!(ValidatorSet::default().leader(&seed, 0, 0).is_none());
+    }
+
+    #[test]
+    // проверяет, что частота выбора пропорциональна стейку, а валидатор без стейка не выбирается.
+    fn test_leader_is_stake_weighted() {
+        let (small, large, empty) = (validator(1), validator(3), validator(0));
+        let set = ValidatorSet::new(vec![small.clone(), large.clone(), empty.clone()]);
+        let mut large_rounds = 0;
+        for round in 0..4000 {
+            let leader = set.leader(&Vec::new(), 1, round).unwrap();
+            assert_ne!(leader.public_key, empty.public_key);
+            if leader.public_key == large.public_key {
+                large_rounds += 1;
+            }
+        }
+        // Ожидается 3/4 раундов.
+        assert!((2800..3200).contains(&large_rounds), "{}", large_rounds);
+    }
+
+    #[test]
+    // проверяет, что общий стейк двух валидаторов с наибольшим стейком считается без
+    // обрезки и ни один из них в одиночку не составляет кворум.
+    fn test_quorum_with_largest_stakes() {
+        let (first, second) = (validator(u64::MAX), validator(u64::MAX));
+        let set = ValidatorSet::new(vec![first.clone(), second]);
+        assert_eq!(set.total_stake(), 2 * u128::from(u64::MAX));
+        assert!(!set.is_quorum(u128::from(set.stake_of(&first.public_key))));
+        assert!(set.is_quorum(set.total_stake()));
+    }
+}